@@ -3,13 +3,21 @@ use ic_ledger_types::AccountIdentifier;
 use icrc_ledger_types::icrc1::account::Account;
 use tiny_keccak::{Hasher, Sha3};
 
+use crate::chain::btc::{
+    account_to_p2pkh_address, account_to_p2tr_address, account_to_p2wpkh_address,
+};
+
 #[derive(CandidType)]
 pub struct Addresses {
     pub icrc1: Account,
     pub icrc1_string: String,
     pub account_identifier: AccountIdentifier,
     pub account_identifier_string: String,
+    // p2wpkh, used as the default deposit address
     pub bitcoin: String,
+    pub bitcoin_legacy: String,
+    // spent through the key path
+    pub bitcoin_taproot: String,
 }
 
 impl From<[u8; 32]> for Addresses {
@@ -21,13 +29,17 @@ impl From<[u8; 32]> for Addresses {
             owner: id,
             subaccount: Some(subaccount),
         };
-        let bitcoin = "".to_string(); // account_to_p2pkh_address(&account);
+        let bitcoin = account_to_p2wpkh_address(&account);
+        let bitcoin_legacy = account_to_p2pkh_address(&account);
+        let bitcoin_taproot = account_to_p2tr_address(&account);
         Addresses {
             icrc1: account,
             icrc1_string: account.to_string(),
             account_identifier,
             account_identifier_string: account_identifier.to_string(),
             bitcoin,
+            bitcoin_legacy,
+            bitcoin_taproot,
         }
     }
 }
@@ -41,3 +53,15 @@ impl From<&Principal> for Addresses {
         Self::from(hash)
     }
 }
+
+impl Addresses {
+    // escrow holding the funds raised by a launch
+    pub fn for_launch(launch_id: u128) -> Self {
+        let mut hash = [0u8; 32];
+        let mut hasher = Sha3::v256();
+        hasher.update(b"launch");
+        hasher.update(&launch_id.to_be_bytes());
+        hasher.finalize(&mut hash);
+        Self::from(hash)
+    }
+}
@@ -1,11 +1,20 @@
-use bitcoin::{address::NetworkUnchecked, Address, Network};
+use std::cell::RefCell;
+
+use bitcoin::{
+    address::NetworkUnchecked, consensus::serialize, secp256k1::Secp256k1, Address,
+    CompressedPublicKey, Network, PublicKey, Transaction, XOnlyPublicKey,
+};
 use ic_cdk::api::management_canister::bitcoin::{
-    bitcoin_get_current_fee_percentiles, BitcoinNetwork, GetCurrentFeePercentilesRequest,
+    bitcoin_get_current_fee_percentiles, bitcoin_send_transaction, BitcoinNetwork,
+    GetCurrentFeePercentilesRequest, SendTransactionRequest,
 };
 use icrc_ledger_types::icrc1::account::Account;
 use utils::{account_to_derivation_path, derive_public_key, ripemd160, sha256};
 
-use crate::state::read_config;
+use crate::{
+    state::{config::FeePriority, read_config},
+    EcdsaPublicKey,
+};
 
 pub mod runestone;
 pub mod signer;
@@ -57,22 +66,90 @@ pub fn account_to_p2pkh_address(account: &Account) -> String {
     })
 }
 
-pub async fn get_fee_per_vbyte() -> u64 {
+pub fn to_network(network: BitcoinNetwork) -> Network {
+    match network {
+        BitcoinNetwork::Mainnet => Network::Bitcoin,
+        BitcoinNetwork::Testnet => Network::Testnet,
+        BitcoinNetwork::Regtest => Network::Regtest,
+    }
+}
+
+pub fn account_to_p2wpkh_address(account: &Account) -> String {
+    read_config(|config| {
+        let network = to_network(config.bitcoin_network());
+        let ecdsa_public_key = config.ecdsa_public_key();
+        let path = account_to_derivation_path(account);
+        let derived_public_key = derive_public_key(&ecdsa_public_key, &path).public_key;
+        let public_key = CompressedPublicKey::from_slice(&derived_public_key)
+            .expect("derived public key should be compressed");
+        Address::p2wpkh(&public_key, network).to_string()
+    })
+}
+
+// BIP-86 style, the derived schnorr key is the internal key and there's no
+// script tree. the output key is signed for with a BIP-341 tweak, see
+// `schnorr_sign_key_path`
+pub fn account_to_p2tr_address(account: &Account) -> String {
+    read_config(|config| {
+        let network = to_network(config.bitcoin_network());
+        let schnorr_public_key = config.schnorr_public_key();
+        let schnorr_public_key = EcdsaPublicKey {
+            public_key: schnorr_public_key.public_key,
+            chain_code: schnorr_public_key.chain_code,
+        };
+        let path = account_to_derivation_path(account);
+        let derived_public_key = derive_public_key(&schnorr_public_key, &path).public_key;
+        let internal_key: XOnlyPublicKey = PublicKey::from_slice(&derived_public_key)
+            .expect("derived public key should be valid")
+            .into();
+        Address::p2tr(&Secp256k1::verification_only(), internal_key, None, network).to_string()
+    })
+}
+
+thread_local! {
+    // last fee percentiles reported by the bitcoin canister, queries can't
+    // fetch them so they price with these
+    static FEE_PERCENTILES: RefCell<Vec<u64>> = RefCell::default();
+}
+
+// refreshes the cached fee percentiles, on failure the previous ones are kept
+pub async fn refresh_fee_percentiles() -> Result<(), String> {
     let network = read_config(|config| config.bitcoin_network());
-    // Get fee percentiles from previous transactions to estimate our own fee.
     let fee_percentiles =
         bitcoin_get_current_fee_percentiles(GetCurrentFeePercentilesRequest { network })
             .await
-            .unwrap()
+            .map_err(|(code, msg)| format!("failed to fetch fee percentiles: {:?} {}", code, msg))?
             .0;
+    FEE_PERCENTILES.with_borrow_mut(|percentiles| *percentiles = fee_percentiles);
+    Ok(())
+}
 
-    if fee_percentiles.is_empty() {
-        // There are no fee percentiles. This case can only happen on a regtest
-        // network where there are no non-coinbase transactions. In this case,
-        // we use a default of 2000 millisatoshis/byte (i.e. 2 satoshi/byte)
-        2000
-    } else {
-        // Choose the 50th percentile for sending fees.
-        fee_percentiles[50]
+// fee rate for `priority` according to the fee policy and the cached
+// percentiles, in millisatoshis per vbyte
+pub fn cached_fee_per_vbyte(priority: FeePriority) -> u64 {
+    let (policy, network) = read_config(|config| (config.fee_policy(), config.bitcoin_network()));
+    if network == BitcoinNetwork::Regtest {
+        return policy.fee_per_vbytes(&[], priority);
     }
+    FEE_PERCENTILES.with_borrow(|percentiles| policy.fee_per_vbytes(percentiles, priority))
+}
+
+pub async fn fee_per_vbyte(priority: FeePriority) -> u64 {
+    let _ = refresh_fee_percentiles().await;
+    cached_fee_per_vbyte(priority)
+}
+
+pub async fn get_fee_per_vbyte() -> u64 {
+    fee_per_vbyte(FeePriority::Medium).await
+}
+
+pub async fn send_transaction(txn: &Transaction) -> Result<(), String> {
+    let network = read_config(|config| config.bitcoin_network());
+    let transaction = serialize(txn);
+    bitcoin_send_transaction(SendTransactionRequest {
+        network,
+        transaction,
+    })
+    .await
+    .map_err(|(code, msg)| format!("failed to send transaction: {:?} {}", code, msg))
 }
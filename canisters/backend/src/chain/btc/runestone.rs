@@ -1,22 +1,63 @@
-mod decimal;
+pub mod decimal;
+pub mod distribute;
 pub mod etch;
 mod inscription;
+pub mod mint;
 
 use std::str::FromStr;
 
-use bitcoin::Amount;
-use ordinals::{Etching, SpacedRune};
+use candid::CandidType;
+use ordinals::{Etching, SpacedRune, Terms};
+use serde::Deserialize;
 
 const DEFAULT_POSTAGE: u64 = 10_000;
-const TARGET_POSTAGE: Amount = Amount::from_sat(10_000);
-const MAX_STANDARD_OP_RETURN_SIZE: usize = 83;
+pub const MAX_STANDARD_OP_RETURN_SIZE: usize = 83;
+
+// open mint terms of an etching, `amount` is in whole tokens like the supply
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub struct MintTerms {
+    pub amount: u128,
+    pub cap: u128,
+    pub height_start: Option<u64>,
+    pub height_end: Option<u64>,
+    pub offset_start: Option<u64>,
+    pub offset_end: Option<u64>,
+}
+
+fn validate_window(start: Option<u64>, end: Option<u64>, window: &str) -> Result<(), String> {
+    match (start, end) {
+        (Some(start), Some(end)) if start >= end => {
+            Err(format!("{} window must start before it ends", window))
+        }
+        _ => Ok(()),
+    }
+}
+
+pub fn validate_terms(terms: MintTerms, unit: u128) -> Result<Terms, String> {
+    if terms.amount == 0 || terms.cap == 0 {
+        return Err(String::from("mint amount and cap must be over 0"));
+    }
+    validate_window(terms.height_start, terms.height_end, "height")?;
+    validate_window(terms.offset_start, terms.offset_end, "offset")?;
+    let amount = terms
+        .amount
+        .checked_mul(unit)
+        .ok_or_else(|| String::from("mint amount overflows"))?;
+    Ok(Terms {
+        amount: Some(amount),
+        cap: Some(terms.cap),
+        height: (terms.height_start, terms.height_end),
+        offset: (terms.offset_start, terms.offset_end),
+    })
+}
 
 pub fn validate_etching(
     runename: &str,
     symbol: Option<u32>,
     divisibility: u8,
     total_supply: u128,
-) -> Result<(SpacedRune, u128, Option<char>), String> {
+    terms: Option<MintTerms>,
+) -> Result<(SpacedRune, u128, Option<char>, Option<Terms>), String> {
     let spaced_rune = match SpacedRune::from_str(runename) {
         Err(_) => return Err("Failed to convert into Spaced Rune".to_string()),
         Ok(sr) => sr,
@@ -32,9 +73,26 @@ pub fn validate_etching(
         ));
     }
 
-    let total_supply = total_supply * 10u128.pow(divisibility as u32);
+    let unit = 10u128.pow(divisibility as u32);
+    let total_supply = total_supply
+        .checked_mul(unit)
+        .ok_or_else(|| String::from("Supply overflows"))?;
+
+    let terms = terms.map(|terms| validate_terms(terms, unit)).transpose()?;
+    // premine and every mint together can't exceed what a rune can hold
+    let mintable = match terms {
+        None => 0,
+        Some(terms) => terms
+            .cap
+            .unwrap_or_default()
+            .checked_mul(terms.amount.unwrap_or_default())
+            .ok_or_else(|| String::from("Mintable supply overflows"))?,
+    };
+    let supply = total_supply
+        .checked_add(mintable)
+        .ok_or_else(|| String::from("Supply overflows"))?;
 
-    if total_supply == 0 {
+    if supply == 0 {
         return Err(String::from("Supply must be over 0"));
     }
 
@@ -48,5 +106,5 @@ pub fn validate_etching(
             Some(symbol)
         }
     };
-    Ok((spaced_rune, total_supply, symbol))
+    Ok((spaced_rune, total_supply, symbol, terms))
 }
@@ -0,0 +1,44 @@
+use bitcoin::ScriptBuf;
+use ordinals::{Edict, RuneId, Runestone};
+
+// runestone sending `allocations[i]` of the rune to output `i`. as many edicts
+// are packed as fit in `max_size` bytes, whatever isn't allocated goes to the
+// output right after the packed ones. an allocation of 0 would move the whole
+// balance, so those have to be filtered out beforehand.
+// returns the runestone and the number of allocations packed
+pub fn distribution_runestone(
+    rune_id: RuneId,
+    allocations: &[u128],
+    max_size: usize,
+) -> (ScriptBuf, usize) {
+    let encipher = |count: usize| {
+        Runestone {
+            edicts: allocations[..count]
+                .iter()
+                .enumerate()
+                .map(|(output, amount)| Edict {
+                    id: rune_id,
+                    amount: *amount,
+                    output: output as u32,
+                })
+                .collect(),
+            etching: None,
+            mint: None,
+            pointer: Some(count as u32),
+        }
+        .encipher()
+    };
+
+    // the size only grows with the number of edicts
+    let (mut packed, mut upper) = (0, allocations.len());
+    while packed < upper {
+        let count = (packed + upper + 1) / 2;
+        if encipher(count).len() <= max_size {
+            packed = count;
+        } else {
+            upper = count - 1;
+        }
+    }
+
+    (encipher(packed), packed)
+}
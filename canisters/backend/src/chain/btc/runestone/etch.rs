@@ -1,31 +1,59 @@
+use std::time::Duration;
+
 use bitcoin::{
     absolute::LockTime,
-    key::{constants::SCHNORR_SIGNATURE_SIZE, Secp256k1},
+    consensus::{deserialize, serialize},
+    hashes::Hash,
+    key::Secp256k1,
     opcodes,
     script::Builder,
-    taproot::{self, ControlBlock, LeafVersion, TaprootBuilder},
+    taproot::{ControlBlock, LeafVersion, TaprootBuilder},
     transaction::Version,
     Address, Amount, Network, OutPoint, PublicKey, Script, ScriptBuf, Sequence, Transaction, TxIn,
-    TxOut, Witness, XOnlyPublicKey,
+    TxOut, Txid, Witness, XOnlyPublicKey,
+};
+use candid::CandidType;
+use ic_cdk::api::management_canister::bitcoin::{
+    bitcoin_get_utxos, BitcoinNetwork, GetUtxosRequest, Outpoint, Utxo, UtxoFilter,
 };
-use ic_cdk::api::management_canister::bitcoin::{BitcoinNetwork, Utxo};
 use icrc_ledger_types::icrc1::account::Account;
-use ordinals::{Etching, Runestone, SpacedRune};
-
-use crate::state::read_config;
+use ordinals::{Etching, Rune, Runestone, SpacedRune, Terms};
 
-use super::{
-    inscription::Inscription, DEFAULT_POSTAGE, MAX_STANDARD_OP_RETURN_SIZE, TARGET_POSTAGE,
+use crate::{
+    chain::btc::{
+        address_validation, send_transaction,
+        signer::{
+            ecdsa::{ecdsa_sign_transaction, mock_ecdsa_signature},
+            schnorr::{mock_schnorr_signature, schnorr_sign_script_path},
+        },
+        transaction::{build_transaction_with_fee, plan_transaction},
+        utils::slice_to_txid,
+    },
+    state::{
+        read_config, read_launch_manager, read_pending_reveals, read_utxo_manager,
+        reveal_manager::PendingReveal, txn_manager::SpentInput, utxo_manager::PendingSpend,
+        write_launch_manager, write_pending_reveals,
+    },
+    txn_handler::TransactionType,
+    updater::txn_watcher::register_transaction,
 };
 
+use super::{inscription::Inscription, DEFAULT_POSTAGE, MAX_STANDARD_OP_RETURN_SIZE};
+
+// roughly one block, the reveal is retried until the commit has matured
+const REVEAL_CHECK_INTERVAL: Duration = Duration::from_secs(600);
+// failed attempts double the interval, up to 16 times it
+const MAX_REVEAL_BACKOFF: u32 = 4;
+const COMMIT_INPUT_INDEX: usize = 0;
+
+// the unsigned reveal along with its vsize once signed
 fn build_reveal_transaction(
     commit_input_index: usize,
     control_block: &ControlBlock,
-    fee_per_vbytes: u64,
     output: Vec<TxOut>,
     input: Vec<OutPoint>,
     script: &Script,
-) -> (Transaction, Amount) {
+) -> (Transaction, u64) {
     let reveal_txn = Transaction {
         input: input
             .into_iter()
@@ -40,31 +68,15 @@ fn build_reveal_transaction(
         lock_time: LockTime::ZERO,
         version: Version(2),
     };
-    let fee = {
-        let mut reveal_txn = reveal_txn.clone();
-
-        for (index, txin) in reveal_txn.input.iter_mut().enumerate() {
-            if index == commit_input_index {
-                txin.witness.push(
-                    taproot::Signature::from_slice(&[0; SCHNORR_SIGNATURE_SIZE])
-                        .unwrap()
-                        .to_vec(),
-                );
-                txin.witness.push(script);
-                txin.witness.push(control_block.serialize());
-            } else {
-                txin.witness = Witness::from_slice(&[&[0; SCHNORR_SIGNATURE_SIZE]]);
-            }
-        }
-
-        let vsize = reveal_txn.vsize() as u64;
-        Amount::from_sat((vsize * fee_per_vbytes) / 1000)
-    };
+    let vsize =
+        mock_schnorr_signature(&reveal_txn, commit_input_index, script, control_block).vsize();
 
-    (reveal_txn, fee)
+    (reveal_txn, vsize as u64)
 }
 
+#[derive(Clone)]
 pub struct EtchingArgs {
+    pub launch_id: u128,
     pub reveal_address: Address,
     pub logo: Option<Vec<u8>>,
     pub content_type: Option<Vec<u8>>,
@@ -72,6 +84,7 @@ pub struct EtchingArgs {
     pub premine: u128,
     pub divisibility: u8,
     pub symbol: Option<char>,
+    pub terms: Option<Terms>,
     pub turbo: bool,
     pub fee_payer: Address,
     pub fee_payer_account: Account,
@@ -79,64 +92,65 @@ pub struct EtchingArgs {
     pub fee_per_vbytes: u64,
 }
 
-pub fn etch(
-    EtchingArgs {
-        reveal_address,
-        logo,
-        content_type,
-        spaced_rune,
-        premine,
-        divisibility,
-        symbol,
-        turbo,
-        fee_payer,
-        fee_payer_account,
-        postage,
-        fee_per_vbytes,
-    }: EtchingArgs,
-) {
-    let postage = Amount::from_sat(postage.unwrap_or(DEFAULT_POSTAGE));
-    let SpacedRune { rune, spacers } = spaced_rune;
-    let inscription = Inscription::new(logo, content_type, rune);
+// everything about an etching which doesn't depend on the commit's inputs
+struct PreparedEtching {
+    reveal_script: ScriptBuf,
+    control_block: ControlBlock,
+    commit_address: Address,
+    reveal_output: Vec<TxOut>,
+    reveal_vsize: u64,
+    reveal_fee: Amount,
+    postage: Amount,
+}
+
+impl PreparedEtching {
+    // the commit output pays for the reveal and the premine's postage
+    fn commit_value(&self) -> Amount {
+        self.reveal_fee + self.postage
+    }
+}
 
-    let (mut reveal_input, mut reveal_output) = (vec![OutPoint::null()], vec![]);
+fn prepare_etching(args: &EtchingArgs) -> Result<PreparedEtching, String> {
+    let SpacedRune { rune, spacers } = args.spaced_rune;
+    let inscription = Inscription::new(args.logo.clone(), args.content_type.clone(), rune);
+
+    let postage = Amount::from_sat(args.postage.unwrap_or(DEFAULT_POSTAGE));
+    let mut reveal_output = vec![];
 
     let etching = Etching {
-        divisibility: Some(divisibility),
-        premine: Some(premine),
+        divisibility: Some(args.divisibility),
+        premine: Some(args.premine),
         rune: Some(rune),
         spacers: Some(spacers),
-        symbol,
-        turbo,
-        terms: None, // this will make rune unmintable
+        symbol: args.symbol,
+        turbo: args.turbo,
+        terms: args.terms, // without terms the rune is unmintable
     };
 
-    let vout;
-
-    if premine > 0 {
-        let output = reveal_output.len() as u32;
+    if args.premine > 0 {
         reveal_output.push(TxOut {
-            script_pubkey: reveal_address.script_pubkey(),
-            value: TARGET_POSTAGE,
+            script_pubkey: args.reveal_address.script_pubkey(),
+            value: postage,
         });
-        vout = output;
     }
 
     let runestone = Runestone {
         edicts: vec![],
         etching: Some(etching),
         mint: None,
-        pointer: (premine > 0).then_some(reveal_output.len() as u32 - 1),
+        pointer: (args.premine > 0).then_some(reveal_output.len() as u32 - 1),
     };
 
     let enciphered = runestone.encipher();
     if enciphered.len() > MAX_STANDARD_OP_RETURN_SIZE {
-        ic_cdk::trap("runestone greater than maximum OP_RETURN size");
+        return Err(String::from(
+            "runestone greater than maximum OP_RETURN size",
+        ));
     }
 
     reveal_output.push(TxOut {
         value: Amount::ZERO,
-        script_pubkey: enciphered.clone(),
+        script_pubkey: enciphered,
     });
 
     let (schnorr_public_key, network) = read_config(|config| {
@@ -168,29 +182,341 @@ pub fn etch(
         .control_block(&(reveal_script.clone(), LeafVersion::TapScript))
         .expect("should compute control block");
 
-    let commit_tx_address = Address::p2tr_tweaked(taproot_spend_info.output_key(), network);
-
-    let commit_input_index = 0;
+    let commit_address = Address::p2tr_tweaked(taproot_spend_info.output_key(), network);
 
-    let (_, reveal_fee) = build_reveal_transaction(
-        commit_input_index,
+    let (_, reveal_vsize) = build_reveal_transaction(
+        COMMIT_INPUT_INDEX,
         &control_block,
-        fee_per_vbytes,
         reveal_output.clone(),
-        reveal_input.clone(),
+        vec![OutPoint::null()],
         &reveal_script,
     );
+    let reveal_fee = Amount::from_sat((reveal_vsize * args.fee_per_vbytes) / 1000);
 
-    let mut target_value = reveal_fee;
-    // for premining
-    target_value += TARGET_POSTAGE;
+    Ok(PreparedEtching {
+        reveal_script,
+        control_block,
+        commit_address,
+        reveal_output,
+        reveal_vsize,
+        reveal_fee,
+        postage,
+    })
+}
+
+#[derive(CandidType)]
+pub struct EtchingQuote {
+    pub fee_per_vbytes: u64,
+    pub commit_vsize: u64,
+    pub commit_fee: u64,
+    pub reveal_vsize: u64,
+    pub reveal_fee: u64,
+    pub postage: u64,
+    // bytes the logo adds to the reveal and the part of the reveal fee they cost
+    pub logo_bytes: u64,
+    pub logo_fee: u64,
+    // what the fee payer has to hold for the commit, its change excluded
+    pub total_required: u64,
+}
+
+#[derive(CandidType)]
+pub struct EtchingCost {
+    pub fee_per_vbytes: u64,
+    pub commit_fee: u64,
+    pub reveal_fee: u64,
+}
+
+impl From<EtchingQuote> for EtchingCost {
+    fn from(quote: EtchingQuote) -> Self {
+        Self {
+            fee_per_vbytes: quote.fee_per_vbytes,
+            commit_fee: quote.commit_fee,
+            reveal_fee: quote.reveal_fee,
+        }
+    }
+}
+
+// simulates an etching the same way `etch` builds it but without taking any
+// utxos. when the fee payer can't cover it yet, the commit is priced with a
+// single deposit of exactly the required amount
+pub fn quote_etching(args: &EtchingArgs) -> Result<EtchingQuote, String> {
+    let prepared = prepare_etching(args)?;
+    let output = vec![TxOut {
+        script_pubkey: prepared.commit_address.script_pubkey(),
+        value: prepared.commit_value(),
+    }];
+    let available =
+        read_utxo_manager(|manager| manager.get_bitcoin_utxos(&args.fee_payer.to_string()));
+    let plan = |available: &[Utxo]| {
+        plan_transaction(
+            &args.fee_payer,
+            available,
+            &[],
+            output.clone(),
+            args.fee_per_vbytes,
+        )
+    };
+    let (commit_txn, utxos) = match plan(&available) {
+        Ok(planned) => planned,
+        Err(required) => plan(&[Utxo {
+            outpoint: Outpoint {
+                txid: vec![0; 32],
+                vout: 0,
+            },
+            value: required,
+            height: 0,
+        }])
+        .map_err(|required| format!("can't price the commit. required: {} sats", required))?,
+    };
+    let spent: u64 = utxos.iter().map(|utxo| utxo.value).sum();
+    let outputs: Amount = commit_txn.output.iter().map(|txout| txout.value).sum();
+    let commit_fee = spent - outputs.to_sat();
+
+    // the content type is paid for with or without the logo
+    let without_logo = prepare_etching(&EtchingArgs {
+        logo: None,
+        ..args.clone()
+    })?;
+    let logo_bytes = (prepared.reveal_script.len() - without_logo.reveal_script.len()) as u64;
+
+    Ok(EtchingQuote {
+        fee_per_vbytes: args.fee_per_vbytes,
+        commit_vsize: mock_ecdsa_signature(&commit_txn, &args.fee_payer).vsize() as u64,
+        commit_fee,
+        reveal_vsize: prepared.reveal_vsize,
+        reveal_fee: prepared.reveal_fee.to_sat(),
+        postage: prepared.postage.to_sat(),
+        logo_bytes,
+        logo_fee: (prepared.reveal_fee - without_logo.reveal_fee).to_sat(),
+        total_required: prepared.commit_value().to_sat() + commit_fee,
+    })
+}
+
+// the signed commit, the utxos it spends and the reveal to store once the
+// commit is broadcast
+pub async fn etch(args: EtchingArgs) -> (Transaction, Vec<Utxo>, PendingReveal, PendingSpend) {
+    let prepared = match prepare_etching(&args) {
+        Err(err) => ic_cdk::trap(&err),
+        Ok(prepared) => prepared,
+    };
+    let EtchingArgs {
+        launch_id,
+        fee_payer,
+        fee_payer_account,
+        fee_per_vbytes,
+        ..
+    } = args;
+    let target_value = prepared.commit_value();
 
-    build_commit_transaction_with_fee(
+    let (commit_txn, utxos, spend) = match build_commit_transaction_with_fee(
         &fee_payer,
-        commit_tx_address.script_pubkey(),
+        prepared.commit_address.script_pubkey(),
         fee_per_vbytes,
         target_value,
+    ) {
+        Err(required) => ic_cdk::trap(&format!(
+            "insufficient balance for etching. required: {} sats",
+            required
+        )),
+        Ok(result) => result,
+    };
+
+    // legacy inputs are part of the txid, so the reveal has to spend the signed commit
+    let commit_txn =
+        ecdsa_sign_transaction(&fee_payer_account, &fee_payer, commit_txn, &utxos).await;
+
+    let commit_vout = 0;
+    let reveal_input = vec![OutPoint {
+        txid: commit_txn.compute_txid(),
+        vout: commit_vout,
+    }];
+
+    let (reveal_txn, _) = build_reveal_transaction(
+        COMMIT_INPUT_INDEX,
+        &prepared.control_block,
+        prepared.reveal_output,
+        reveal_input,
+        &prepared.reveal_script,
+    );
+
+    let pending_reveal = PendingReveal {
+        launch_id,
+        commit_txid: commit_txn.compute_txid().to_string(),
+        commit_vout,
+        commit_value: target_value.to_sat(),
+        commit_address: prepared.commit_address.to_string(),
+        reveal_script: prepared.reveal_script.to_bytes(),
+        control_block: prepared.control_block.serialize(),
+        reveal_txn: serialize(&reveal_txn),
+        reveal_txid: None,
+    };
+
+    (commit_txn, utxos, pending_reveal, spend)
+}
+
+// points the reveal at another version of its commit, e.g. after the commit
+// was replaced with a higher fee. the pending reveal is moved to the new txid
+pub fn rederive_reveal(pending_reveal: &mut PendingReveal, commit_txid: Txid) {
+    let mut reveal_txn: Transaction =
+        deserialize(&pending_reveal.reveal_txn).expect("should decode reveal transaction");
+    reveal_txn.input[0].previous_output.txid = commit_txid;
+    pending_reveal.reveal_txn = serialize(&reveal_txn);
+
+    let previous = std::mem::replace(&mut pending_reveal.commit_txid, commit_txid.to_string());
+    write_pending_reveals(|reveals| {
+        reveals.remove(&previous);
+        reveals.insert(commit_txid.to_string(), pending_reveal.clone());
+    });
+}
+
+pub fn schedule_reveal(commit_txid: String) {
+    schedule_reveal_after(commit_txid, REVEAL_CHECK_INTERVAL, 0);
+}
+
+// `failures` counts the attempts in a row which failed, each one doubles the
+// delay before the next
+fn schedule_reveal_after(mut commit_txid: String, delay: Duration, failures: u32) {
+    ic_cdk_timers::set_timer(delay, move || {
+        ic_cdk::spawn(async move {
+            match try_reveal(&mut commit_txid).await {
+                Ok(true) => {}
+                Ok(false) => schedule_reveal(commit_txid),
+                Err(_) => {
+                    let failures = failures + 1;
+                    let delay = REVEAL_CHECK_INTERVAL * 2u32.pow(failures.min(MAX_REVEAL_BACKOFF));
+                    schedule_reveal_after(commit_txid, delay, failures)
+                }
+            }
+        })
+    });
+}
+
+// broadcasts the reveal once the commit has matured. `false` means it has to
+// be checked again later, `true` that nothing is left to reveal. `commit_txid`
+// is updated when another version of the commit got mined
+async fn try_reveal(commit_txid: &mut String) -> Result<bool, String> {
+    let mut pending_reveal = match read_pending_reveals(|reveals| reveals.get(commit_txid)) {
+        None => return Ok(true),
+        Some(pending_reveal) => pending_reveal,
+    };
+    if pending_reveal.reveal_txid.is_some() {
+        return Ok(true);
+    }
+    let network = read_config(|config| config.bitcoin_network());
+
+    let commit_utxos = bitcoin_get_utxos(GetUtxosRequest {
+        address: pending_reveal.commit_address.clone(),
+        network,
+        filter: Some(UtxoFilter::MinConfirmations(
+            Runestone::COMMIT_CONFIRMATIONS as u32,
+        )),
+    })
+    .await
+    .map_err(|(code, msg)| format!("failed to fetch utxos: {:?} {}", code, msg))?
+    .0
+    .utxos;
+
+    // the commit address only ever receives the commit, whichever version of
+    // it got mined if it was replaced
+    let commit_utxo = match commit_utxos.into_iter().find(|utxo| {
+        utxo.outpoint.vout == pending_reveal.commit_vout
+            && utxo.value == pending_reveal.commit_value
+    }) {
+        None => return Ok(false),
+        Some(utxo) => utxo,
+    };
+    let mined_txid = slice_to_txid(&commit_utxo.outpoint.txid);
+    if mined_txid.to_string() != *commit_txid {
+        rederive_reveal(&mut pending_reveal, mined_txid);
+        *commit_txid = mined_txid.to_string();
+        let _ = write_launch_manager(|manager| {
+            manager.update_launch(pending_reveal.launch_id, |launch| {
+                launch.commit_txid = mined_txid.to_string()
+            })
+        });
+    }
+
+    let runename = read_launch_manager(|manager| manager.get_launch(pending_reveal.launch_id))
+        .map(|launch| launch.runename)
+        .ok_or_else(|| String::from("launch not found"))?;
+    let spaced_rune: SpacedRune = runename
+        .parse()
+        .map_err(|err| format!("invalid rune name {}: {}", runename, err))?;
+    check_rune_commitment(&pending_reveal, spaced_rune.rune)?;
+
+    let reveal_txn = sign_reveal_transaction(&pending_reveal).await?;
+    send_transaction(&reveal_txn).await?;
+    let reveal_txid = reveal_txn.compute_txid().to_string();
+    pending_reveal.reveal_txid.replace(reveal_txid.clone());
+    write_pending_reveals(|reveals| {
+        reveals.insert(pending_reveal.commit_txid.clone(), pending_reveal.clone())
+    });
+    register_transaction(
+        TransactionType::Reveal {
+            launch_id: pending_reveal.launch_id,
+            commit_txid: pending_reveal.commit_txid.clone(),
+        },
+        &reveal_txn,
+        vec![SpentInput {
+            utxo: commit_utxo,
+            address: pending_reveal.commit_address.clone(),
+            account: None,
+        }],
+        // the fee of a reveal without premine can't be bumped
+        (!reveal_txn.output[0].script_pubkey.is_op_return()).then_some(0),
     );
+    // the premine is etched into the first output
+    let premine_utxo = reveal_txn
+        .output
+        .first()
+        .filter(|txout| !txout.script_pubkey.is_op_return())
+        .map(|txout| Utxo {
+            outpoint: Outpoint {
+                txid: reveal_txn.compute_txid().to_byte_array().to_vec(),
+                vout: 0,
+            },
+            value: txout.value.to_sat(),
+            height: 0,
+        });
+    let _ = write_launch_manager(|manager| {
+        manager.update_launch(pending_reveal.launch_id, |launch| {
+            launch.reveal_txid.replace(reveal_txid);
+            launch.premine_utxo = premine_utxo;
+        })
+    });
+    Ok(true)
+}
+
+// the etching is only valid if the reveal commits to the rune, checked before
+// anything gets signed
+pub fn check_rune_commitment(pending_reveal: &PendingReveal, rune: Rune) -> Result<(), String> {
+    let reveal_script = Script::from_bytes(&pending_reveal.reveal_script);
+    let commitment = rune.commitment();
+    if Inscription::from_tapscript(reveal_script)
+        .iter()
+        .any(|inscription| inscription.rune.as_ref() == Some(&commitment))
+    {
+        Ok(())
+    } else {
+        Err(String::from("reveal doesn't carry the rune commitment"))
+    }
+}
+
+pub async fn sign_reveal_transaction(
+    pending_reveal: &PendingReveal,
+) -> Result<Transaction, String> {
+    let reveal_txn: Transaction =
+        deserialize(&pending_reveal.reveal_txn).expect("should decode reveal transaction");
+    let reveal_script = ScriptBuf::from_bytes(pending_reveal.reveal_script.clone());
+    let control_block =
+        ControlBlock::decode(&pending_reveal.control_block).expect("should decode control block");
+    let commit_address = address_validation(&pending_reveal.commit_address)?;
+    let prevouts = [TxOut {
+        value: Amount::from_sat(pending_reveal.commit_value),
+        script_pubkey: commit_address.script_pubkey(),
+    }];
+
+    schnorr_sign_script_path(reveal_txn, 0, &prevouts, &reveal_script, &control_block).await
 }
 
 fn build_commit_transaction_with_fee(
@@ -198,9 +524,7 @@ fn build_commit_transaction_with_fee(
     recipient: ScriptBuf,
     fee_per_vbytes: u64,
     target: Amount,
-) -> Result<(Transaction, Vec<Utxo>), u64> {
-    let (mut input, mut output) = (vec![], vec![]);
-
+) -> Result<(Transaction, Vec<Utxo>, PendingSpend), u64> {
     if !recipient.is_op_return() {
         let dust_value = recipient.minimal_non_dust();
 
@@ -209,11 +533,5 @@ fn build_commit_transaction_with_fee(
         }
     }
 
-    let txn = Transaction {
-        input,
-        output,
-        version: Version(2),
-        lock_time: LockTime::ZERO,
-    };
-    todo!()
+    build_transaction_with_fee(fee_payer, &recipient, fee_per_vbytes, target)
 }
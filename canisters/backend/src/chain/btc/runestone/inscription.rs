@@ -1,4 +1,11 @@
-use bitcoin::{constants::MAX_SCRIPT_ELEMENT_SIZE, opcodes, script};
+use std::collections::BTreeMap;
+
+use bitcoin::{
+    constants::MAX_SCRIPT_ELEMENT_SIZE,
+    opcodes,
+    script::{self, Instruction},
+    Script, Witness,
+};
 use ordinals::Rune;
 use tag::Tag;
 
@@ -56,4 +63,193 @@ impl Inscription {
 
         builder.push_opcode(opcodes::all::OP_ENDIF)
     }
+
+    // inscriptions revealed by the tapscript of a script path spend
+    pub fn from_witness(witness: &Witness) -> Vec<Self> {
+        witness
+            .tapscript()
+            .map(Self::from_tapscript)
+            .unwrap_or_default()
+    }
+
+    // decodes every `OP_FALSE OP_IF "ord" ... OP_ENDIF` envelope in `script`
+    pub fn from_tapscript(script: &Script) -> Vec<Self> {
+        let mut instructions = script.instructions().peekable();
+        let mut inscriptions = vec![];
+
+        while let Some(Ok(instruction)) = instructions.next() {
+            if !matches!(instruction, Instruction::PushBytes(push) if push.is_empty()) {
+                continue;
+            }
+            if !matches!(
+                instructions.peek(),
+                Some(Ok(Instruction::Op(opcodes::all::OP_IF)))
+            ) {
+                continue;
+            }
+            instructions.next();
+            if !matches!(
+                instructions.peek(),
+                Some(Ok(Instruction::PushBytes(push))) if push.as_bytes() == PROTOCOL_ID
+            ) {
+                continue;
+            }
+            instructions.next();
+
+            let mut payload = vec![];
+            let closed = loop {
+                match instructions.next() {
+                    Some(Ok(Instruction::Op(opcodes::all::OP_ENDIF))) => break true,
+                    Some(Ok(Instruction::PushBytes(push))) => {
+                        payload.push(push.as_bytes().to_vec())
+                    }
+                    Some(Ok(Instruction::Op(op))) => match push_num(op) {
+                        None => break false,
+                        Some(value) => payload.push(vec![value]),
+                    },
+                    _ => break false,
+                }
+            };
+            // an envelope interrupted by other opcodes or never closed reveals nothing
+            if closed {
+                inscriptions.push(Self::from_payload(&payload));
+            }
+        }
+
+        inscriptions
+    }
+
+    // payload is a list of tag and value pushes, the body follows an empty tag
+    fn from_payload(payload: &[Vec<u8>]) -> Self {
+        let body = payload
+            .iter()
+            .enumerate()
+            .position(|(index, push)| index % 2 == 0 && push.is_empty());
+
+        let mut fields: BTreeMap<&[u8], Vec<&[u8]>> = BTreeMap::new();
+        let mut incomplete_field = false;
+        for field in payload[..body.unwrap_or(payload.len())].chunks(2) {
+            match field {
+                [tag, value] => fields
+                    .entry(tag.as_slice())
+                    .or_default()
+                    .push(value.as_slice()),
+                _ => incomplete_field = true,
+            }
+        }
+        // chunked fields are split over several pushes of the same tag
+        let duplicate_field = fields
+            .iter()
+            .any(|(tag, values)| values.len() > 1 && *tag != Tag::Metadata.bytes().as_slice());
+
+        let content_encoding = Tag::ContentEncoding.take(&mut fields);
+        let content_type = Tag::ContentType.take(&mut fields);
+        let delegate = Tag::Delegate.take(&mut fields);
+        let metadata = Tag::Metadata.take(&mut fields);
+        let metaprotocol = Tag::Metaprotocol.take(&mut fields);
+        let parents = Tag::Parent.take_array(&mut fields);
+        let pointer = Tag::Pointer.take(&mut fields);
+        let rune = Tag::Rune.take(&mut fields);
+
+        // odd tags may be ignored, even ones change how the inscription is read
+        let unrecognized_even_field = fields
+            .keys()
+            .any(|tag| tag.first().is_some_and(|lsb| lsb % 2 == 0));
+
+        Self {
+            body: body.map(|index| payload[index + 1..].concat()),
+            content_encoding,
+            content_type,
+            delegate,
+            duplicate_field,
+            incomplete_field,
+            metadata,
+            metaprotocol,
+            parents,
+            pointer,
+            rune,
+            unrecognized_even_field,
+        }
+    }
+}
+
+// small numbers pushed with their own opcode
+fn push_num(op: opcodes::Opcode) -> Option<u8> {
+    let code = op.to_u8();
+    if op == opcodes::all::OP_PUSHNUM_NEG1 {
+        Some(0x81)
+    } else if (opcodes::all::OP_PUSHNUM_1.to_u8()..=opcodes::all::OP_PUSHNUM_16.to_u8())
+        .contains(&code)
+    {
+        Some(code - opcodes::all::OP_PUSHNUM_1.to_u8() + 1)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reveal_script(inscription: &Inscription) -> bitcoin::ScriptBuf {
+        let builder = script::Builder::new()
+            .push_slice([2u8; 32])
+            .push_opcode(opcodes::all::OP_CHECKSIG);
+        inscription
+            .append_reveal_script_to_builder(builder)
+            .into_script()
+    }
+
+    #[test]
+    fn roundtrips_reveal_script() {
+        let rune = Rune(99246114928149462);
+        let inscription =
+            Inscription::new(Some(b"hello".to_vec()), Some(b"text/plain".to_vec()), rune);
+        let decoded = Inscription::from_tapscript(&reveal_script(&inscription));
+
+        assert_eq!(decoded.len(), 1);
+        let decoded = &decoded[0];
+        assert_eq!(decoded.rune, Some(rune.commitment()));
+        assert_eq!(decoded.body, Some(b"hello".to_vec()));
+        assert_eq!(decoded.content_type, Some(b"text/plain".to_vec()));
+        assert!(!decoded.duplicate_field);
+        assert!(!decoded.incomplete_field);
+        assert!(!decoded.unrecognized_even_field);
+    }
+
+    #[test]
+    fn roundtrips_chunked_body() {
+        let body = vec![7u8; MAX_SCRIPT_ELEMENT_SIZE * 2 + 1];
+        let inscription = Inscription::new(Some(body.clone()), None, Rune(0));
+        let decoded = Inscription::from_tapscript(&reveal_script(&inscription));
+
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].body, Some(body));
+        assert_eq!(decoded[0].rune, Some(Rune(0).commitment()));
+    }
+
+    #[test]
+    fn reads_the_tapscript_of_a_witness() {
+        let rune = Rune(12345);
+        let script = reveal_script(&Inscription::new(None, None, rune));
+        let witness = Witness::from_slice(&[vec![1u8; 64], script.into_bytes(), vec![0xc0; 33]]);
+        let decoded = Inscription::from_witness(&witness);
+
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].rune, Some(rune.commitment()));
+        assert_eq!(decoded[0].body, None);
+    }
+
+    #[test]
+    fn ignores_unclosed_envelope() {
+        let script = script::Builder::new()
+            .push_opcode(opcodes::OP_FALSE)
+            .push_opcode(opcodes::all::OP_IF)
+            .push_slice(PROTOCOL_ID)
+            .push_slice(Tag::Rune.bytes())
+            .push_slice([1u8])
+            .into_script();
+
+        assert!(Inscription::from_tapscript(&script).is_empty());
+    }
 }
@@ -0,0 +1,52 @@
+use bitcoin::ScriptBuf;
+use ordinals::{RuneId, Runestone, Terms};
+
+// checks a mint confirming at `height` against the terms the same way ord does
+// when indexing it, returning the amount minted. `etching_height` is the block
+// of the etching and `mints` the mints made so far
+pub fn validate_mint(
+    terms: &Terms,
+    etching_height: u64,
+    height: u64,
+    mints: u128,
+) -> Result<u128, String> {
+    if let Some(cap) = terms.cap {
+        if mints >= cap {
+            return Err(String::from("mint cap reached"));
+        }
+    }
+
+    let offset_start = terms
+        .offset
+        .0
+        .map(|offset| etching_height.saturating_add(offset));
+    let offset_end = terms
+        .offset
+        .1
+        .map(|offset| etching_height.saturating_add(offset));
+    for start in [terms.height.0, offset_start].into_iter().flatten() {
+        if height < start {
+            return Err(format!("mint opens at block {}", start));
+        }
+    }
+    for end in [terms.height.1, offset_end].into_iter().flatten() {
+        if height >= end {
+            return Err(format!("mint closed at block {}", end));
+        }
+    }
+
+    terms
+        .amount
+        .ok_or_else(|| String::from("rune has no mint amount"))
+}
+
+// mints into the first output
+pub fn mint_runestone(rune_id: RuneId) -> ScriptBuf {
+    Runestone {
+        edicts: vec![],
+        etching: None,
+        mint: Some(rune_id),
+        pointer: Some(0),
+    }
+    .encipher()
+}
@@ -1,2 +1,51 @@
+use bitcoin::{Address, AddressType, Amount, Transaction, TxOut};
+use ic_cdk::api::management_canister::bitcoin::Utxo;
+use icrc_ledger_types::icrc1::account::Account;
+
+use ecdsa::{ecdsa_sign_transaction, mock_ecdsa_signature};
+use schnorr::{mock_key_path_signature, schnorr_sign_key_path};
+
+use super::utils::slice_to_txid;
+
 pub mod ecdsa;
 pub mod schnorr;
+
+// deposit addresses are signed for with ecdsa, except p2tr ones which are
+// spent through the key path. see `ecdsa_sign_transaction` for the rest
+pub fn mock_signature(txn: &Transaction, address: &Address) -> Transaction {
+    match address.address_type() {
+        Some(AddressType::P2tr) => mock_key_path_signature(txn),
+        _ => mock_ecdsa_signature(txn, address),
+    }
+}
+
+// key path sighashes commit to every spent output, so a p2tr address has to
+// pay for all inputs
+pub async fn sign_transaction(
+    account: &Account,
+    address: &Address,
+    txn: Transaction,
+    utxos: &[Utxo],
+) -> Result<Transaction, String> {
+    if address.address_type() != Some(AddressType::P2tr) {
+        return Ok(ecdsa_sign_transaction(account, address, txn, utxos).await);
+    }
+    let prevouts = txn
+        .input
+        .iter()
+        .map(|input| {
+            utxos
+                .iter()
+                .find(|utxo| {
+                    slice_to_txid(&utxo.outpoint.txid) == input.previous_output.txid
+                        && utxo.outpoint.vout == input.previous_output.vout
+                })
+                .map(|utxo| TxOut {
+                    value: Amount::from_sat(utxo.value),
+                    script_pubkey: address.script_pubkey(),
+                })
+                .ok_or_else(|| String::from("every input has to spend from the p2tr address"))
+        })
+        .collect::<Result<Vec<TxOut>, String>>()?;
+    schnorr_sign_key_path(account, txn, &prevouts).await
+}
@@ -1,17 +1,41 @@
 use bitcoin::{
+    hashes::Hash,
     script::{Builder, PushBytesBuf},
-    sighash::EcdsaSighashType,
-    Sequence, Transaction, TxIn, Witness,
+    sighash::{EcdsaSighashType, SighashCache},
+    Address, AddressType, Amount, ScriptBuf, Sequence, Transaction, TxIn, Witness,
 };
-use ic_cdk::api::management_canister::ecdsa::{
-    sign_with_ecdsa, SignWithEcdsaArgument, SignWithEcdsaResponse,
+use ic_cdk::api::management_canister::{
+    bitcoin::Utxo,
+    ecdsa::{sign_with_ecdsa, SignWithEcdsaArgument, SignWithEcdsaResponse},
 };
+use icrc_ledger_types::icrc1::account::Account;
 
 use crate::state::read_config;
 
 use crate::chain::btc::utils::*;
 
-pub fn mock_ecdsa_signature(txn: &Transaction) -> Transaction {
+// p2pkh inputs carry the signature in script_sig while p2wpkh (BIP-143)
+// inputs carry it in the witness
+fn unlock_input(input: &mut TxIn, address: &Address, der_signature: Vec<u8>, pubkey: Vec<u8>) {
+    match address.address_type() {
+        Some(AddressType::P2pkh) => {
+            let signature_as_pushbytes = PushBytesBuf::try_from(der_signature).unwrap();
+            let publickey_as_pushbytes = PushBytesBuf::try_from(pubkey).unwrap();
+            input.script_sig = Builder::new()
+                .push_slice(signature_as_pushbytes)
+                .push_slice(publickey_as_pushbytes)
+                .into_script();
+            input.witness.clear();
+        }
+        Some(AddressType::P2wpkh) => {
+            input.script_sig = ScriptBuf::new();
+            input.witness = Witness::from_slice(&[der_signature, pubkey]);
+        }
+        _ => ic_cdk::trap("address type can't be signed with ecdsa"),
+    }
+}
+
+pub fn mock_ecdsa_signature(txn: &Transaction, address: &Address) -> Transaction {
     let pubkey = read_config(|config| {
         let ecdsa_key = config.ecdsa_public_key();
         let path = vec![];
@@ -24,17 +48,14 @@ pub fn mock_ecdsa_signature(txn: &Transaction) -> Transaction {
             let signature = vec![255; 64];
             let mut der_signature = sec1_to_der(signature);
             der_signature.push(EcdsaSighashType::All.to_u32() as u8);
-            let signature_as_pushbytes = PushBytesBuf::try_from(der_signature).unwrap();
-            let publickey_as_pushbytes = PushBytesBuf::try_from(pubkey.clone()).unwrap();
-            TxIn {
+            let mut input = TxIn {
                 previous_output: input.previous_output,
                 witness: Witness::new(),
                 sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
-                script_sig: Builder::new()
-                    .push_slice(signature_as_pushbytes)
-                    .push_slice(publickey_as_pushbytes)
-                    .into_script(),
-            }
+                script_sig: ScriptBuf::new(),
+            };
+            unlock_input(&mut input, address, der_signature, pubkey.clone());
+            input
         })
         .collect::<Vec<TxIn>>();
     Transaction {
@@ -45,6 +66,55 @@ pub fn mock_ecdsa_signature(txn: &Transaction) -> Transaction {
     }
 }
 
+// signs the inputs spending one of `utxos`, which belong to `address`. other
+// inputs are left untouched so transactions spending from several addresses
+// can be signed address by address
+pub async fn ecdsa_sign_transaction(
+    account: &Account,
+    address: &Address,
+    mut txn: Transaction,
+    utxos: &[Utxo],
+) -> Transaction {
+    let path = account_to_derivation_path(account);
+    let pubkey = read_config(|config| {
+        let ecdsa_key = config.ecdsa_public_key();
+        derive_public_key(&ecdsa_key, &path).public_key
+    });
+    let derivation_path: Vec<Vec<u8>> = path.into_iter().map(|index| index.into_vec()).collect();
+    let script_pubkey = address.script_pubkey();
+    let mut txn_cache = SighashCache::new(txn.clone());
+
+    for (index, input) in txn.input.iter_mut().enumerate() {
+        let Some(utxo) = utxos.iter().find(|utxo| {
+            slice_to_txid(&utxo.outpoint.txid) == input.previous_output.txid
+                && utxo.outpoint.vout == input.previous_output.vout
+        }) else {
+            continue;
+        };
+        let sighash = match address.address_type() {
+            Some(AddressType::P2wpkh) => {
+                let value = Amount::from_sat(utxo.value);
+                txn_cache
+                    .p2wpkh_signature_hash(index, &script_pubkey, value, EcdsaSighashType::All)
+                    .expect("failed to compute sighash")
+                    .to_byte_array()
+            }
+            _ => txn_cache
+                .legacy_signature_hash(index, &script_pubkey, EcdsaSighashType::All.to_u32())
+                .expect("failed to compute sighash")
+                .to_byte_array(),
+        };
+        let signature = ecdsa_sign(sighash.to_vec(), derivation_path.clone())
+            .await
+            .signature;
+        let mut der_signature = sec1_to_der(signature);
+        der_signature.push(EcdsaSighashType::All.to_u32() as u8);
+        unlock_input(input, address, der_signature, pubkey.clone());
+    }
+
+    txn
+}
+
 pub async fn ecdsa_sign(
     message_hash: Vec<u8>,
     derivation_path: Vec<Vec<u8>>,
@@ -1,15 +1,175 @@
+use bitcoin::{
+    hashes::Hash,
+    key::constants::SCHNORR_SIGNATURE_SIZE,
+    sighash::{Prevouts, SighashCache, TapSighash, TapSighashType},
+    taproot::{self, ControlBlock, LeafVersion, TapLeafHash},
+    Script, ScriptBuf, Transaction, TxOut, Witness,
+};
+use candid::{CandidType, Principal};
 use ic_cdk::api::management_canister::schnorr::{
-    sign_with_schnorr, SignWithSchnorrArgument, SignWithSchnorrResponse,
+    sign_with_schnorr, SchnorrKeyId, SignWithSchnorrArgument, SignWithSchnorrResponse,
 };
+use icrc_ledger_types::icrc1::account::Account;
+
+use crate::{chain::btc::utils::account_to_derivation_path, state::read_config};
+
+// what ic-cdk attaches to `sign_with_schnorr`, anything unused is refunded
+const SIGN_WITH_SCHNORR_FEE: u128 = 26_153_846_153;
+
+// `sign_with_schnorr` with the `aux` field ic-cdk doesn't expose yet
+#[derive(CandidType)]
+struct SignWithSchnorrAuxArgument {
+    message: Vec<u8>,
+    derivation_path: Vec<Vec<u8>>,
+    key_id: SchnorrKeyId,
+    aux: Option<SignWithSchnorrAux>,
+}
+
+#[derive(CandidType)]
+enum SignWithSchnorrAux {
+    #[serde(rename = "bip341")]
+    Bip341 { merkle_root_hash: Vec<u8> },
+}
+
+fn script_path_witness(signature: &[u8], script: &Script, control_block: &ControlBlock) -> Witness {
+    let mut witness = Witness::new();
+    witness.push(signature);
+    witness.push(script);
+    witness.push(control_block.serialize());
+    witness
+}
+
+// fills every input with a witness of the same size as the real one, so that
+// vsize (and therefore fee) can be estimated before signing
+pub fn mock_schnorr_signature(
+    txn: &Transaction,
+    script_input_index: usize,
+    script: &Script,
+    control_block: &ControlBlock,
+) -> Transaction {
+    let signature = taproot::Signature::from_slice(&[0; SCHNORR_SIGNATURE_SIZE])
+        .unwrap()
+        .to_vec();
+    let mut txn = txn.clone();
+    for (index, txin) in txn.input.iter_mut().enumerate() {
+        if index == script_input_index {
+            txin.witness = script_path_witness(&signature, script, control_block);
+        } else {
+            txin.witness = Witness::from_slice(&[&signature]);
+        }
+    }
+    txn
+}
+
+// signs the script path spend of the inscription leaf. the leaf commits to the
+// canister's root schnorr key, hence the empty derivation path
+pub async fn schnorr_sign_script_path(
+    mut txn: Transaction,
+    input_index: usize,
+    prevouts: &[TxOut],
+    script: &Script,
+    control_block: &ControlBlock,
+) -> Result<Transaction, String> {
+    let leaf_hash = TapLeafHash::from_script(script, LeafVersion::TapScript);
+    let sighash = SighashCache::new(&txn)
+        .taproot_script_spend_signature_hash(
+            input_index,
+            &Prevouts::All(prevouts),
+            leaf_hash,
+            TapSighashType::Default,
+        )
+        .expect("failed to compute sighash");
+
+    let signature = schnorr_sign(sighash.as_byte_array().to_vec(), vec![])
+        .await?
+        .signature;
+
+    txn.input[input_index].witness = script_path_witness(&signature, script, control_block);
+    Ok(txn)
+}
+
+// a key path spend carries nothing but the signature, with the default sighash
+// type it's the bare 64 bytes
+fn key_path_witness(signature: &[u8]) -> Witness {
+    Witness::from_slice(&[signature])
+}
 
-use crate::state::read_config;
+fn key_path_sighash(txn: &Transaction, input_index: usize, prevouts: &[TxOut]) -> TapSighash {
+    SighashCache::new(txn)
+        .taproot_key_spend_signature_hash(
+            input_index,
+            &Prevouts::All(prevouts),
+            TapSighashType::Default,
+        )
+        .expect("failed to compute sighash")
+}
 
-pub fn mock_schnorr_signature() {}
+// same as `mock_schnorr_signature` for transactions only spending p2tr deposit
+// addresses
+pub fn mock_key_path_signature(txn: &Transaction) -> Transaction {
+    let signature = [0; SCHNORR_SIGNATURE_SIZE];
+    let mut txn = txn.clone();
+    for txin in txn.input.iter_mut() {
+        txin.script_sig = ScriptBuf::new();
+        txin.witness = key_path_witness(&signature);
+    }
+    txn
+}
+
+// key path spend of the p2tr deposit address of `account`. `prevouts` are the
+// outputs spent by every input, all of them paying to that address. the
+// threshold key applies the BIP-341 tweak of the address itself
+pub async fn schnorr_sign_key_path(
+    account: &Account,
+    mut txn: Transaction,
+    prevouts: &[TxOut],
+) -> Result<Transaction, String> {
+    let derivation_path: Vec<Vec<u8>> = account_to_derivation_path(account)
+        .into_iter()
+        .map(|index| index.into_vec())
+        .collect();
+    let unsigned = txn.clone();
+    for (index, input) in txn.input.iter_mut().enumerate() {
+        let sighash = key_path_sighash(&unsigned, index, prevouts);
+        let signature =
+            schnorr_sign_bip341(sighash.as_byte_array().to_vec(), derivation_path.clone())
+                .await?
+                .signature;
+        input.script_sig = ScriptBuf::new();
+        input.witness = key_path_witness(&signature);
+    }
+    Ok(txn)
+}
+
+// signs for the derived key tweaked by an empty script tree
+async fn schnorr_sign_bip341(
+    message: Vec<u8>,
+    derivation_path: Vec<Vec<u8>>,
+) -> Result<SignWithSchnorrResponse, String> {
+    let key_id = read_config(|config| config.schnorrkeyid());
+    let args = SignWithSchnorrAuxArgument {
+        message,
+        derivation_path,
+        key_id,
+        aux: Some(SignWithSchnorrAux::Bip341 {
+            merkle_root_hash: vec![],
+        }),
+    };
+    ic_cdk::api::call::call_with_payment128(
+        Principal::management_canister(),
+        "sign_with_schnorr",
+        (args,),
+        SIGN_WITH_SCHNORR_FEE,
+    )
+    .await
+    .map(|(response,): (SignWithSchnorrResponse,)| response)
+    .map_err(|(code, msg)| format!("failed to sign with schnorr: {:?} {}", code, msg))
+}
 
 pub async fn schnorr_sign(
     message: Vec<u8>,
     derivation_path: Vec<Vec<u8>>,
-) -> SignWithSchnorrResponse {
+) -> Result<SignWithSchnorrResponse, String> {
     let key_id = read_config(|config| config.schnorrkeyid());
 
     sign_with_schnorr(SignWithSchnorrArgument {
@@ -18,6 +178,66 @@ pub async fn schnorr_sign(
         key_id,
     })
     .await
-    .unwrap()
-    .0
+    .map(|(response,)| response)
+    .map_err(|(code, msg)| format!("failed to sign with schnorr: {:?} {}", code, msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        absolute::LockTime,
+        key::{Keypair, TapTweak},
+        secp256k1::{Message, Secp256k1},
+        transaction::Version,
+        Address, Amount, Network, OutPoint, Sequence, TxIn, Txid,
+    };
+
+    use super::*;
+
+    // signs like the threshold key does with the bip341 aux
+    #[test]
+    fn key_path_spend_verifies_against_the_output_key() {
+        let secp = Secp256k1::new();
+        let keypair = Keypair::from_seckey_slice(&secp, &[7; 32]).unwrap();
+        let (internal_key, _) = keypair.x_only_public_key();
+        let address = Address::p2tr(&secp, internal_key, None, Network::Regtest);
+        let prevouts = vec![TxOut {
+            value: Amount::from_sat(10_000),
+            script_pubkey: address.script_pubkey(),
+        }];
+        let txn = Transaction {
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: Txid::all_zeros(),
+                    vout: 0,
+                },
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(9_000),
+                script_pubkey: address.script_pubkey(),
+            }],
+            version: Version(2),
+            lock_time: LockTime::ZERO,
+        };
+
+        let sighash = key_path_sighash(&txn, 0, &prevouts);
+        let message = Message::from_digest(sighash.to_byte_array());
+        let tweaked = keypair.tap_tweak(&secp, None).to_inner();
+        let signature = secp.sign_schnorr_no_aux_rand(&message, &tweaked);
+        let mut signed = txn.clone();
+        signed.input[0].witness = key_path_witness(signature.as_ref());
+
+        let witness: Vec<&[u8]> = signed.input[0].witness.iter().collect();
+        assert_eq!(witness.len(), 1);
+        assert_eq!(witness[0].len(), SCHNORR_SIGNATURE_SIZE);
+        let (output_key, _) = internal_key.tap_tweak(&secp, None);
+        let signature = bitcoin::secp256k1::schnorr::Signature::from_slice(witness[0]).unwrap();
+        assert!(secp
+            .verify_schnorr(&signature, &message, &output_key.to_inner())
+            .is_ok());
+        assert_eq!(mock_key_path_signature(&txn).vsize(), signed.vsize());
+    }
 }
@@ -1 +1,396 @@
-pub fn transfer() {}
+use std::collections::HashMap;
+
+use bitcoin::{
+    absolute::LockTime, transaction::Version, Address, Amount, OutPoint, ScriptBuf, Sequence,
+    Transaction, TxIn, TxOut, Witness,
+};
+use ic_cdk::api::management_canister::bitcoin::Utxo;
+use icrc_ledger_types::icrc1::account::Account;
+use ordinals::{Edict, Runestone};
+
+use crate::{
+    state::{
+        read_utxo_manager,
+        utxo_manager::{select_utxos, PendingSpend, SelectionStrategy},
+        write_utxo_manager,
+    },
+    updater::{
+        rune_indexer::{index_transaction, RuneAllocation},
+        RuneId,
+    },
+};
+
+use super::{
+    signer::{mock_signature, sign_transaction},
+    utils::slice_to_txid,
+    DUST_THRESHOLD,
+};
+
+// pays `target` to `recipient` from the utxos of `payer`, who also covers the
+// fee. the selected utxos are taken out of the utxo manager until the returned
+// spend is dropped without being broadcast. on failure the total amount
+// required is returned
+pub fn build_transaction_with_fee(
+    payer: &Address,
+    recipient: &ScriptBuf,
+    fee_per_vbytes: u64,
+    target: Amount,
+) -> Result<(Transaction, Vec<Utxo>, PendingSpend), u64> {
+    let output = vec![TxOut {
+        script_pubkey: recipient.clone(),
+        value: target,
+    }];
+    build_transaction_with_outputs(payer, output, fee_per_vbytes)
+}
+
+// same as `build_transaction_with_fee` for any set of outputs, the change is
+// appended after them
+pub fn build_transaction_with_outputs(
+    payer: &Address,
+    output: Vec<TxOut>,
+    fee_per_vbytes: u64,
+) -> Result<(Transaction, Vec<Utxo>, PendingSpend), u64> {
+    build_transaction_spending(payer, &[], output, fee_per_vbytes)
+}
+
+// `spending` are utxos of `payer` which have to be spent first, e.g. runic
+// ones. their value counts towards the outputs and the fee. the returned utxos
+// are all inputs of the transaction, in order
+pub fn build_transaction_spending(
+    payer: &Address,
+    spending: &[Utxo],
+    output: Vec<TxOut>,
+    fee_per_vbytes: u64,
+) -> Result<(Transaction, Vec<Utxo>, PendingSpend), u64> {
+    let addr = payer.to_string();
+    let available = read_utxo_manager(|manager| manager.get_bitcoin_utxos(&addr));
+    let (txn, utxos) = plan_transaction(payer, &available, spending, output, fee_per_vbytes)?;
+    let selected = &utxos[spending.len()..];
+    write_utxo_manager(|manager| manager.remove_bitcoin_utxos(&addr, selected));
+    let spend = PendingSpend::new(&addr, selected);
+    Ok((txn, utxos, spend))
+}
+
+// selects from `available` without taking anything out of the utxo manager,
+// so fees can be quoted exactly as they would be paid. input sets that need no
+// change output are preferred
+pub fn plan_transaction(
+    payer: &Address,
+    available: &[Utxo],
+    spending: &[Utxo],
+    output: Vec<TxOut>,
+    fee_per_vbytes: u64,
+) -> Result<(Transaction, Vec<Utxo>), u64> {
+    let target: Amount = output.iter().map(|txout| txout.value).sum();
+    let spent = Amount::from_sat(spending.iter().map(|utxo| utxo.value).sum());
+    let mut fee = Amount::ZERO;
+    // overshooting by less than this isn't worth a change output
+    let change = TxOut {
+        value: Amount::ZERO,
+        script_pubkey: payer.script_pubkey(),
+    };
+    let cost_of_change = (change.size() as u64 * fee_per_vbytes) / 1000 + DUST_THRESHOLD;
+
+    loop {
+        let required = (target + fee).checked_sub(spent).unwrap_or(Amount::ZERO);
+        let mut utxos = spending.to_vec();
+        match select_utxos(
+            available,
+            required.to_sat(),
+            SelectionStrategy::BranchAndBound { cost_of_change },
+        ) {
+            None => return Err(required.to_sat()),
+            Some(selected) => utxos.extend(selected),
+        };
+
+        let txn = build_transaction(payer, output.clone(), target, fee, &utxos);
+        let required_fee = transaction_fee(&mock_signature(&txn, payer), fee_per_vbytes);
+
+        // dropping the change output can only shrink the transaction, so
+        // overpaying by a few sats is preferred over oscillating forever
+        if required_fee <= fee {
+            return Ok((txn, utxos));
+        }
+        fee = required_fee;
+    }
+}
+
+pub fn utxos_to_inputs(utxos: &[Utxo]) -> Vec<TxIn> {
+    utxos
+        .iter()
+        .map(|utxo| TxIn {
+            previous_output: OutPoint {
+                txid: slice_to_txid(&utxo.outpoint.txid),
+                vout: utxo.outpoint.vout,
+            },
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            script_sig: ScriptBuf::new(),
+            witness: Witness::new(),
+        })
+        .collect()
+}
+
+fn build_transaction(
+    payer: &Address,
+    mut output: Vec<TxOut>,
+    target: Amount,
+    fee: Amount,
+    utxos: &[Utxo],
+) -> Transaction {
+    let total_spent: u64 = utxos.iter().map(|utxo| utxo.value).sum();
+    let remaining = total_spent - target.to_sat() - fee.to_sat();
+    if remaining > DUST_THRESHOLD {
+        output.push(TxOut {
+            script_pubkey: payer.script_pubkey(),
+            value: Amount::from_sat(remaining),
+        });
+    }
+
+    Transaction {
+        input: utxos_to_inputs(utxos),
+        output,
+        version: Version(2),
+        lock_time: LockTime::ZERO,
+    }
+}
+
+// sends every bitcoin utxo of `from` to `to`, the fee is taken from the amount
+pub async fn transfer_all(
+    account: &Account,
+    from: &Address,
+    to: &Address,
+    fee_per_vbytes: u64,
+) -> Result<(Transaction, PendingSpend), String> {
+    let addr = from.to_string();
+    let utxos = read_utxo_manager(|manager| manager.get_bitcoin_utxos(&addr));
+    if utxos.is_empty() {
+        return Err(String::from("no bitcoin to withdraw"));
+    }
+    let total = Amount::from_sat(utxos.iter().map(|utxo| utxo.value).sum());
+    let mut txn = Transaction {
+        input: utxos_to_inputs(&utxos),
+        output: vec![TxOut {
+            script_pubkey: to.script_pubkey(),
+            value: total,
+        }],
+        version: Version(2),
+        lock_time: LockTime::ZERO,
+    };
+    let fee = transaction_fee(&mock_signature(&txn, from), fee_per_vbytes);
+    txn.output[0].value = total
+        .checked_sub(fee)
+        .filter(|amount| amount.to_sat() >= DUST_THRESHOLD)
+        .ok_or_else(|| format!("balance doesn't cover the fee of {} sats", fee.to_sat()))?;
+
+    write_utxo_manager(|manager| manager.remove_bitcoin_utxos(&addr, &utxos));
+    let spend = PendingSpend::new(&addr, &utxos);
+    Ok((sign_transaction(account, from, txn, &utxos).await?, spend))
+}
+
+// pays `amount` of the rune from the runic utxos of `from` with an edict, runes
+// left over go back to `from` through the pointer. fees are paid in bitcoin
+// from the same address. nothing is awaited before the utxos are taken out of
+// the utxo manager, so trapping on an error leaves them untouched. the rune
+// balances of the outputs are returned along with the signed transaction
+pub async fn transfer_runes(
+    account: &Account,
+    from: &Address,
+    to: &Address,
+    rune_id: RuneId,
+    amount: u128,
+    fee_per_vbytes: u64,
+) -> Result<(Transaction, RuneAllocation, PendingSpend), String> {
+    if amount == 0 {
+        return Err(String::from("amount must be over 0"));
+    }
+    let addr = from.to_string();
+    let utxo_runes = read_utxo_manager(|manager| manager.get_utxo_runes(&addr));
+    let runic_utxos = write_utxo_manager(|manager| {
+        let (mut selected, mut total) = (vec![], 0);
+        while total < amount {
+            match manager.get_runic_utxo(&addr, rune_id) {
+                None => break,
+                Some(runic) => {
+                    total += runic.balance;
+                    selected.push(runic.utxo);
+                }
+            }
+        }
+        (total >= amount).then_some(selected)
+    })
+    .ok_or_else(|| String::from("insufficient rune balance"))?;
+    let mut spend = PendingSpend::new(&addr, &runic_utxos);
+
+    let runestone = Runestone {
+        edicts: vec![Edict {
+            id: rune_id.into(),
+            amount,
+            output: 0,
+        }],
+        etching: None,
+        mint: None,
+        pointer: Some(1),
+    };
+    let output = vec![
+        TxOut {
+            value: to.script_pubkey().minimal_non_dust(),
+            script_pubkey: to.script_pubkey(),
+        },
+        TxOut {
+            value: from.script_pubkey().minimal_non_dust(),
+            script_pubkey: from.script_pubkey(),
+        },
+        TxOut {
+            value: Amount::ZERO,
+            script_pubkey: runestone.encipher(),
+        },
+    ];
+    let (txn, utxos, fees) = build_transaction_spending(from, &runic_utxos, output, fee_per_vbytes)
+        .map_err(|required| format!("insufficient balance. required: {} sats", required))?;
+    spend.merge(fees);
+    let txn = sign_transaction(account, from, txn, &utxos).await?;
+
+    let mut inputs: HashMap<RuneId, u128> = HashMap::new();
+    for utxo in runic_utxos.iter() {
+        for (runeid, balance) in utxo_runes.get(&utxo.outpoint).into_iter().flatten() {
+            *inputs.entry(*runeid).or_default() += balance;
+        }
+    }
+    let allocation = index_transaction(&txn, inputs, None, |_| None);
+    Ok((txn, allocation, spend))
+}
+
+pub fn transaction_fee(signed_txn: &Transaction, fee_per_vbytes: u64) -> Amount {
+    let vsize = signed_txn.vsize() as u64;
+    Amount::from_sat((vsize * fee_per_vbytes) / 1000)
+}
+
+// signed transaction paying `amount` from the deposit address of `account`
+pub async fn transfer(
+    account: &Account,
+    from: &Address,
+    to: &Address,
+    amount: u64,
+    fee_per_vbytes: u64,
+) -> Result<(Transaction, PendingSpend), String> {
+    if amount < DUST_THRESHOLD {
+        return Err(format!("amount must be at least {} sats", DUST_THRESHOLD));
+    }
+    let (txn, utxos, spend) = build_transaction_with_fee(
+        from,
+        &to.script_pubkey(),
+        fee_per_vbytes,
+        Amount::from_sat(amount),
+    )
+    .map_err(|required| format!("insufficient balance. required: {} sats", required))?;
+    Ok((sign_transaction(account, from, txn, &utxos).await?, spend))
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{hashes::Hash, hex::FromHex, CompressedPublicKey, Network, WPubkeyHash};
+    use ic_cdk::api::management_canister::bitcoin::Outpoint;
+
+    use super::*;
+    use crate::{state::write_config, EcdsaPublicKey};
+
+    // the generator point, any valid key prices the same
+    const PUBLIC_KEY: &str = "0279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798";
+
+    fn payer() -> Address {
+        let public_key = Vec::<u8>::from_hex(PUBLIC_KEY).unwrap();
+        write_config(|config| {
+            let mut temp = config.get().clone();
+            temp.ecdsa_public_key.replace(EcdsaPublicKey {
+                public_key: public_key.clone(),
+                chain_code: vec![0; 32],
+            });
+            config.set(temp).expect("failed to set config");
+        });
+        let public_key = CompressedPublicKey::from_slice(&public_key).unwrap();
+        Address::p2wpkh(&public_key, Network::Regtest)
+    }
+
+    fn utxo(id: u8, value: u64) -> Utxo {
+        Utxo {
+            outpoint: Outpoint {
+                txid: vec![id; 32],
+                vout: 0,
+            },
+            value,
+            height: 1,
+        }
+    }
+
+    fn output(value: u64) -> Vec<TxOut> {
+        vec![TxOut {
+            script_pubkey: ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros()),
+            value: Amount::from_sat(value),
+        }]
+    }
+
+    #[test]
+    fn plans_fee_and_change() {
+        let payer = payer();
+        let (txn, utxos) =
+            plan_transaction(&payer, &[utxo(1, 100_000)], &[], output(50_000), 10_000).unwrap();
+
+        // one p2wpkh input and two outputs weigh 141 vbytes at 10 sats each
+        assert_eq!(utxos, vec![utxo(1, 100_000)]);
+        assert_eq!(mock_signature(&txn, &payer).vsize(), 141);
+        assert_eq!(txn.output.len(), 2);
+        assert_eq!(txn.output[0].value, Amount::from_sat(50_000));
+        assert_eq!(txn.output[1].value, Amount::from_sat(48_590));
+        assert_eq!(txn.output[1].script_pubkey, payer.script_pubkey());
+    }
+
+    #[test]
+    fn reports_the_required_amount() {
+        let payer = payer();
+        // without change the transaction weighs 110 vbytes, 1_100 sats short
+        let required =
+            plan_transaction(&payer, &[utxo(1, 51_000)], &[], output(50_000), 10_000).unwrap_err();
+        assert_eq!(required, 51_100);
+    }
+
+    #[test]
+    fn adds_inputs_until_the_fee_is_covered() {
+        let payer = payer();
+        let available = [utxo(1, 30_000), utxo(2, 30_000)];
+        let (txn, utxos) =
+            plan_transaction(&payer, &available, &[], output(50_000), 2_000).unwrap();
+
+        // two inputs and two outputs weigh 209 vbytes at 2 sats each
+        assert_eq!(utxos.len(), 2);
+        assert_eq!(txn.input.len(), 2);
+        assert_eq!(txn.output[1].value, Amount::from_sat(9_582));
+    }
+
+    #[test]
+    fn spends_the_given_utxos_first() {
+        let payer = payer();
+        let (txn, utxos) = plan_transaction(
+            &payer,
+            &[utxo(2, 100_000)],
+            &[utxo(1, 546)],
+            output(50_000),
+            2_000,
+        )
+        .unwrap();
+
+        assert_eq!(utxos, vec![utxo(1, 546), utxo(2, 100_000)]);
+        assert_eq!(txn.input[0].previous_output.txid, slice_to_txid(&[1; 32]));
+    }
+
+    #[test]
+    fn fee_is_rounded_down_to_sats() {
+        let payer = payer();
+        let (txn, _) =
+            plan_transaction(&payer, &[utxo(1, 100_000)], &[], output(50_000), 10_000).unwrap();
+        let signed = mock_signature(&txn, &payer);
+
+        // rates are in millisats per vbyte
+        assert_eq!(transaction_fee(&signed, 1_500), Amount::from_sat(211));
+        assert_eq!(transaction_fee(&signed, 1_000), Amount::from_sat(141));
+    }
+}
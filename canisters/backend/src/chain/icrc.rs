@@ -0,0 +1,118 @@
+use candid::{Nat, Principal};
+use icrc_ledger_types::{
+    icrc1::{
+        account::{Account, Subaccount},
+        transfer::{TransferArg, TransferError},
+    },
+    icrc2::transfer_from::{TransferFromArgs, TransferFromError},
+};
+
+fn to_u64(amount: Nat) -> Result<u64, String> {
+    u64::try_from(amount.0).map_err(|_| String::from("amount doesn't fit in 64 bits"))
+}
+
+pub async fn fee(ledger: Principal) -> Result<u64, String> {
+    let (fee,): (Nat,) = ic_cdk::call(ledger, "icrc1_fee", ())
+        .await
+        .map_err(|(code, msg)| format!("failed to fetch ledger fee: {:?} {}", code, msg))?;
+    to_u64(fee)
+}
+
+pub async fn balance_of(ledger: Principal, account: Account) -> Result<u64, String> {
+    let (balance,): (Nat,) = ic_cdk::call(ledger, "icrc1_balance_of", (account,))
+        .await
+        .map_err(|(code, msg)| format!("failed to fetch ledger balance: {:?} {}", code, msg))?;
+    to_u64(balance)
+}
+
+// pulls `amount` from `from` into `to`, `from` has to have approved this
+// canister beforehand. the ledger fee is paid by `from` on top. returns the
+// block index
+pub async fn transfer_from(
+    ledger: Principal,
+    from: Account,
+    to: Account,
+    amount: u64,
+) -> Result<u64, String> {
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from,
+        to,
+        amount: Nat::from(amount),
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+    let (result,): (Result<Nat, TransferFromError>,) =
+        ic_cdk::call(ledger, "icrc2_transfer_from", (args,))
+            .await
+            .map_err(|(code, msg)| format!("failed to call ledger: {:?} {}", code, msg))?;
+    result
+        .map_err(|err| format!("transfer failed: {:?}", err))
+        .and_then(to_u64)
+}
+
+// pays `amount` from a subaccount of this canister, the ledger fee is taken
+// from the same subaccount. returns the block index
+pub async fn transfer(
+    ledger: Principal,
+    from_subaccount: Option<Subaccount>,
+    to: Account,
+    amount: u64,
+) -> Result<u64, String> {
+    let args = TransferArg {
+        from_subaccount,
+        to,
+        fee: None,
+        created_at_time: None,
+        memo: None,
+        amount: Nat::from(amount),
+    };
+    let (result,): (Result<Nat, TransferError>,) = ic_cdk::call(ledger, "icrc1_transfer", (args,))
+        .await
+        .map_err(|(code, msg)| format!("failed to call ledger: {:?} {}", code, msg))?;
+    result
+        .map_err(|err| format!("transfer failed: {:?}", err))
+        .and_then(to_u64)
+}
+
+// the ledger calls made for raises held on a ledger, a stand-in takes the
+// ledger's place in tests
+#[allow(async_fn_in_trait)]
+pub trait Ledger {
+    async fn fee(&self) -> Result<u64, String>;
+
+    async fn balance_of(&self, account: Account) -> Result<u64, String>;
+
+    async fn transfer_from(&self, from: Account, to: Account, amount: u64) -> Result<u64, String>;
+
+    async fn transfer(
+        &self,
+        from_subaccount: Option<Subaccount>,
+        to: Account,
+        amount: u64,
+    ) -> Result<u64, String>;
+}
+
+impl Ledger for Principal {
+    async fn fee(&self) -> Result<u64, String> {
+        fee(*self).await
+    }
+
+    async fn balance_of(&self, account: Account) -> Result<u64, String> {
+        balance_of(*self, account).await
+    }
+
+    async fn transfer_from(&self, from: Account, to: Account, amount: u64) -> Result<u64, String> {
+        transfer_from(*self, from, to, amount).await
+    }
+
+    async fn transfer(
+        &self,
+        from_subaccount: Option<Subaccount>,
+        to: Account,
+        amount: u64,
+    ) -> Result<u64, String> {
+        transfer(*self, from_subaccount, to, amount).await
+    }
+}
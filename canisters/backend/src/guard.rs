@@ -0,0 +1,66 @@
+use std::ops::{Deref, DerefMut};
+
+// hands `value` to `release` when dropped, unless it's disarmed first. a trap
+// rolls state back to the last await, the suspended call included, and the
+// cleanup then drops the call as it was there, keeping what the drop writes.
+// so anything taken before an await is given back whether the call fails with
+// an error or traps
+#[must_use]
+pub struct OnDrop<T, F: FnOnce(T)> {
+    armed: Option<(T, F)>,
+}
+
+impl<T, F: FnOnce(T)> OnDrop<T, F> {
+    pub fn new(value: T, release: F) -> Self {
+        Self {
+            armed: Some((value, release)),
+        }
+    }
+
+    // keeps what was taken, returning the value without releasing it
+    pub fn disarm(mut self) -> T {
+        let (value, _) = self.armed.take().expect("guard is armed until dropped");
+        value
+    }
+}
+
+impl<T, F: FnOnce(T)> Deref for OnDrop<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.armed.as_ref().expect("guard is armed until dropped").0
+    }
+}
+
+impl<T, F: FnOnce(T)> DerefMut for OnDrop<T, F> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.armed.as_mut().expect("guard is armed until dropped").0
+    }
+}
+
+impl<T, F: FnOnce(T)> Drop for OnDrop<T, F> {
+    fn drop(&mut self) {
+        if let Some((value, release)) = self.armed.take() {
+            release(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[test]
+    fn releases_unless_disarmed() {
+        let released = Cell::new(0);
+        drop(OnDrop::new(1, |value| released.set(released.get() + value)));
+        assert_eq!(released.get(), 1);
+
+        let mut kept = OnDrop::new(1, |value| released.set(released.get() + value));
+        *kept += 1;
+        assert_eq!(kept.disarm(), 2);
+        assert_eq!(released.get(), 1);
+    }
+}
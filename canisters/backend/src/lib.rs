@@ -1,19 +1,42 @@
 mod addresses;
 mod chain;
+mod guard;
 mod memory;
 mod state;
+#[cfg(test)]
+mod test_support;
 mod token_type;
 mod txn_handler;
 mod updater;
 
-use std::time::Duration;
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
 
 use addresses::Addresses;
+use bitcoin::{Amount, TxOut};
 use candid::{CandidType, Principal};
-use chain::btc::{
-    address_validation,
-    runestone::{etch::EtchingArgs, validate_etching},
+use chain::{
+    btc::{
+        address_validation, cached_fee_per_vbyte, get_fee_per_vbyte,
+        runestone::{
+            decimal::Decimal,
+            etch::{etch, quote_etching, schedule_reveal, EtchingArgs, EtchingCost, EtchingQuote},
+            mint::{mint_runestone, validate_mint},
+            validate_etching, validate_terms, MintTerms,
+        },
+        send_transaction,
+        signer::ecdsa::ecdsa_sign_transaction,
+        transaction::{
+            build_transaction_with_fee, build_transaction_with_outputs, transfer, transfer_all,
+            transfer_runes,
+        },
+        DUST_THRESHOLD,
+    },
+    icrc::Ledger,
 };
+use guard::OnDrop;
 use ic_cdk::{
     api::management_canister::{
         bitcoin::BitcoinNetwork,
@@ -27,10 +50,31 @@ use ic_cdk::{
     },
     init, post_upgrade, pre_upgrade, query, update,
 };
+use icrc_ledger_types::icrc1::account::Account;
 use memory::Memory;
 use serde::Deserialize;
-use state::{read_config, write_config};
+use state::{
+    config::{FeePolicy, FeePriority},
+    launch_manager::{
+        allocation_for, Contribution, Launch, LaunchCursor, LaunchFilter, LaunchSort, LaunchStatus,
+    },
+    migrations::{init_schema_version, migrate},
+    read_config, read_launch_manager, read_pending_reveals, read_user_manager, read_utxo_manager,
+    txn_manager::SpentInput,
+    write_config, write_launch_manager, write_pending_reveals, write_user_manager,
+    write_utxo_manager,
+};
 use token_type::TokenType;
+use txn_handler::{SubmittedTxidType, TransactionType};
+use updater::{
+    launch_ticker::start_ticker,
+    ord_canister::get_rune_mints,
+    rune_indexer::{index_transaction, record_runic_outputs},
+    siwb_provider::bind_address,
+    txn_watcher::{accelerate_transaction, register_transaction, start_watcher},
+    utxo_syncer::{start_syncer, sync_address},
+    RuneId,
+};
 
 async fn lazy_ecdsa_schnorr_setup() {
     let (ecdsakeyid, schnorrkeyid) =
@@ -64,6 +108,67 @@ pub struct InitArgs {
     pub bitcoin_network: BitcoinNetwork,
     pub auth: Option<Principal>,
     pub commission_receiver: Option<Principal>,
+    pub commission_bps: Option<u16>,
+    pub ord_indexer: Option<Principal>,
+    pub runestone_size_limit: Option<u32>,
+    pub rbf_timeout_secs: Option<u64>,
+    pub fee_policy: Option<FeePolicy>,
+    pub icp_ledger: Option<Principal>,
+    pub ckbtc_ledger: Option<Principal>,
+    pub siwb_provider: Option<Principal>,
+}
+
+// settings that can be changed when upgrading, `None` keeps the current one
+#[derive(CandidType, Deserialize, Default)]
+pub struct UpgradeArgs {
+    pub auth: Option<Principal>,
+    pub commission_receiver: Option<Principal>,
+    pub commission_bps: Option<u16>,
+    pub ord_indexer: Option<Principal>,
+    pub runestone_size_limit: Option<u32>,
+    pub rbf_timeout_secs: Option<u64>,
+    pub fee_policy: Option<FeePolicy>,
+    pub icp_ledger: Option<Principal>,
+    pub ckbtc_ledger: Option<Principal>,
+    pub siwb_provider: Option<Principal>,
+}
+
+// validated as a whole before any of it is written
+fn apply_settings(
+    UpgradeArgs {
+        auth,
+        commission_receiver,
+        commission_bps,
+        ord_indexer,
+        runestone_size_limit,
+        rbf_timeout_secs,
+        fee_policy,
+        icp_ledger,
+        ckbtc_ledger,
+        siwb_provider,
+    }: UpgradeArgs,
+) -> Result<(), String> {
+    if commission_bps.is_some_and(|bps| bps > 10_000) {
+        return Err(String::from("commission can't be over 100%"));
+    }
+    if let Some(fee_policy) = fee_policy.as_ref() {
+        fee_policy.validate()?;
+    }
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.auth = auth.or(temp.auth);
+        temp.commission_receiver = commission_receiver.or(temp.commission_receiver);
+        temp.commission_bps = commission_bps.or(temp.commission_bps);
+        temp.ord_indexer = ord_indexer.or(temp.ord_indexer);
+        temp.runestone_size_limit = runestone_size_limit.or(temp.runestone_size_limit);
+        temp.rbf_timeout_secs = rbf_timeout_secs.or(temp.rbf_timeout_secs);
+        temp.fee_policy = fee_policy.or(temp.fee_policy);
+        temp.icp_ledger = icp_ledger.or(temp.icp_ledger);
+        temp.ckbtc_ledger = ckbtc_ledger.or(temp.ckbtc_ledger);
+        temp.siwb_provider = siwb_provider.or(temp.siwb_provider);
+        config.set(temp).expect("failed to set config");
+    });
+    Ok(())
 }
 
 #[init]
@@ -72,10 +177,16 @@ pub fn init(
         bitcoin_network,
         auth,
         commission_receiver,
+        commission_bps,
+        ord_indexer,
+        runestone_size_limit,
+        rbf_timeout_secs,
+        fee_policy,
+        icp_ledger,
+        ckbtc_ledger,
+        siwb_provider,
     }: InitArgs,
 ) {
-    let caller = ic_cdk::caller();
-    let auth = auth.unwrap_or(caller);
     let keyname = match bitcoin_network {
         BitcoinNetwork::Mainnet => "key_1".to_string(),
         BitcoinNetwork::Testnet => "test_key_1".to_string(),
@@ -84,24 +195,280 @@ pub fn init(
     write_config(|config| {
         let mut temp = config.get().clone();
         temp.keyname.replace(keyname);
-        temp.auth.replace(auth);
         temp.bitcoin_network.replace(bitcoin_network);
         config.set(temp).expect("failed to set config");
     });
-    ic_cdk_timers::set_timer(Duration::from_secs(0), || {
-        ic_cdk::spawn(lazy_ecdsa_schnorr_setup())
+    let settings = UpgradeArgs {
+        auth: Some(auth.unwrap_or(ic_cdk::caller())),
+        commission_receiver,
+        commission_bps,
+        ord_indexer,
+        runestone_size_limit,
+        rbf_timeout_secs,
+        fee_policy,
+        icp_ledger,
+        ckbtc_ledger,
+        siwb_provider,
+    };
+    if let Err(err) = apply_settings(settings) {
+        ic_cdk::trap(&err)
+    }
+    init_schema_version();
+    start_timers();
+}
+
+// timers don't survive upgrades, so they're armed again from the stable state
+fn start_timers() {
+    if read_config(|config| {
+        config.ecdsa_public_key.is_none() || config.schnorr_public_key.is_none()
+    }) {
+        ic_cdk_timers::set_timer(Duration::from_secs(0), || {
+            ic_cdk::spawn(lazy_ecdsa_schnorr_setup())
+        });
+    }
+    start_syncer();
+    start_ticker();
+    start_watcher();
+    // broadcast reveals are followed by the watcher
+    let unrevealed: Vec<String> = read_pending_reveals(|reveals| {
+        reveals
+            .iter()
+            .filter(|(_, reveal)| reveal.reveal_txid.is_none())
+            .map(|(commit_txid, _)| commit_txid)
+            .collect()
     });
+    for commit_txid in unrevealed {
+        schedule_reveal(commit_txid);
+    }
 }
 
+// all state lives in stable memory
 #[pre_upgrade]
 pub fn pre_upgrade() {}
 
+// the network and key can't change, everything else is kept unless `args`
+// overrides it
 #[post_upgrade]
-pub fn post_upgrade() {}
+pub fn post_upgrade(args: Option<UpgradeArgs>) {
+    if let Err(err) = migrate() {
+        ic_cdk::trap(&err)
+    }
+    if let Err(err) = apply_settings(args.unwrap_or_default()) {
+        ic_cdk::trap(&err)
+    }
+    start_timers();
+}
+
+// anonymous callers would all share the same deposit addresses
+fn authenticated_caller() -> Principal {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        ic_cdk::trap("anonymous caller not allowed")
+    }
+    caller
+}
+
+// refreshes the utxos of the caller's deposit addresses
+#[update]
+pub async fn sync_utxos() {
+    let caller = authenticated_caller();
+    let addresses = Addresses::from(&caller);
+    for addr in [
+        addresses.bitcoin,
+        addresses.bitcoin_legacy,
+        addresses.bitcoin_taproot,
+    ] {
+        write_utxo_manager(|manager| manager.track_address(&addr));
+        if let Err(err) = sync_address(&addr).await {
+            ic_cdk::trap(&err)
+        }
+    }
+}
+
+// where the funds of a contribution are at
+#[derive(CandidType)]
+pub enum ParticipationState {
+    Open,
+    PendingClaim,
+    Claimed { txid: String },
+    PendingRefund,
+    Refunded { txid: String },
+}
+
+#[derive(CandidType)]
+pub struct Participation {
+    pub launch_id: u128,
+    pub runename: String,
+    pub raise_in: TokenType,
+    pub status: LaunchStatus,
+    pub amount: u64,
+    pub allocation: u128,
+    pub state: ParticipationState,
+}
+
+#[derive(CandidType)]
+pub struct RuneHolding {
+    pub rune_id: RuneId,
+    pub runename: Option<String>,
+    pub amount: u128,
+    // known for runes launched through this canister
+    pub divisibility: Option<u8>,
+    // the amount in whole tokens, in base units when the divisibility isn't known
+    pub formatted: String,
+}
+
+#[derive(CandidType)]
+pub struct UserDetail {
+    pub addresses: Addresses,
+    // wallet the user signed in with
+    pub btc_address: Option<String>,
+    pub launches_created: Vec<u128>,
+    pub launches_participated: Vec<Participation>,
+    // held by the deposit addresses
+    pub btc_balance: u64,
+    pub rune_balances: Vec<RuneHolding>,
+}
+
+fn participation(launch: Launch, contribution: Contribution) -> Participation {
+    let state = match launch.status {
+        LaunchStatus::Succeeded | LaunchStatus::Distributed => {
+            match contribution.distribution_txid {
+                None => ParticipationState::PendingClaim,
+                Some(txid) => ParticipationState::Claimed { txid },
+            }
+        }
+        LaunchStatus::Failed | LaunchStatus::Refunded => match contribution.refund_txid {
+            None => ParticipationState::PendingRefund,
+            Some(txid) => ParticipationState::Refunded { txid },
+        },
+        _ => ParticipationState::Open,
+    };
+    Participation {
+        launch_id: launch.launch_id,
+        runename: launch.runename,
+        raise_in: launch.raise_in,
+        status: launch.status,
+        amount: contribution.amount,
+        allocation: contribution.allocation,
+        state,
+    }
+}
+
+// balances come from the last sync of the deposit addresses
+#[query]
+pub fn user_detail(user: Option<Principal>) -> UserDetail {
+    let user = user.unwrap_or_else(ic_cdk::caller);
+    let addresses = Addresses::from(&user);
+    let detail = read_user_manager(|manager| manager.get_user(&user));
+
+    let mut launches_created: Vec<u128> = detail.launch_created.into_iter().collect();
+    launches_created.sort();
+    let mut launch_ids: Vec<u128> = detail.launch_participated.into_iter().collect();
+    launch_ids.sort();
+    let launches_participated = read_launch_manager(|manager| {
+        launch_ids
+            .into_iter()
+            .filter_map(|launch_id| {
+                let launch = manager.get_launch(launch_id)?;
+                let contribution = manager.get_contribution(launch_id, &user)?;
+                Some(participation(launch, contribution))
+            })
+            .collect()
+    });
+
+    let deposit_addresses = [
+        &addresses.bitcoin,
+        &addresses.bitcoin_legacy,
+        &addresses.bitcoin_taproot,
+    ];
+    let (btc_balance, runes) = read_utxo_manager(|manager| {
+        let mut btc_balance = 0;
+        let mut runes: BTreeMap<RuneId, u128> = BTreeMap::new();
+        for addr in deposit_addresses {
+            btc_balance += manager.get_bitcoin_balance(addr);
+            for (rune_id, balance) in manager.get_runic_balances(addr) {
+                *runes.entry(rune_id).or_default() += balance;
+            }
+        }
+        (btc_balance, runes)
+    });
+    let rune_balances = read_launch_manager(|manager| {
+        runes
+            .into_iter()
+            .map(|(rune_id, amount)| {
+                let launch = manager.launch_by_rune(&rune_id);
+                let divisibility = launch.as_ref().map(|launch| launch.divisibility);
+                let formatted = Decimal {
+                    value: amount,
+                    scale: divisibility.unwrap_or(0),
+                }
+                .to_string();
+                RuneHolding {
+                    rune_id,
+                    runename: launch.map(|launch| launch.runename),
+                    amount,
+                    divisibility,
+                    formatted,
+                }
+            })
+            .collect()
+    });
+
+    UserDetail {
+        addresses,
+        btc_address: detail.btc_address,
+        launches_created,
+        launches_participated,
+        btc_balance,
+        rune_balances,
+    }
+}
 
-pub fn user_detail() {}
+#[derive(CandidType, Deserialize)]
+pub struct AllLaunchesArgs {
+    pub cursor: Option<LaunchCursor>,
+    pub limit: Option<u32>,
+    pub filter: Option<LaunchFilter>,
+    // defaults to the start time
+    pub sort_by: Option<LaunchSort>,
+    pub descending: bool,
+}
 
-pub fn all_launches() {}
+#[derive(CandidType)]
+pub struct LaunchPage {
+    pub launches: Vec<Launch>,
+    // passed as `cursor` to get the next page, `None` on the last one
+    pub next_cursor: Option<LaunchCursor>,
+}
+
+const DEFAULT_PAGE_SIZE: u32 = 20;
+const MAX_PAGE_SIZE: u32 = 100;
+
+#[query]
+pub fn all_launches(
+    AllLaunchesArgs {
+        cursor,
+        limit,
+        filter,
+        sort_by,
+        descending,
+    }: AllLaunchesArgs,
+) -> LaunchPage {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE) as usize;
+    let (launches, next_cursor) = read_launch_manager(|manager| {
+        manager.page_launches(
+            &filter.unwrap_or_default(),
+            sort_by.unwrap_or(LaunchSort::StartTime),
+            descending,
+            cursor,
+            limit,
+        )
+    });
+    LaunchPage {
+        launches,
+        next_cursor,
+    }
+}
 
 #[derive(CandidType, Deserialize)]
 pub struct StartLaunchArgs {
@@ -111,6 +478,7 @@ pub struct StartLaunchArgs {
     pub symbol: Option<u32>,
     pub divisibility: u8,
     pub total_supply: u128,
+    pub terms: Option<MintTerms>,
     pub turbo: bool,
     pub website: Option<String>,
     pub x: Option<String>,
@@ -122,6 +490,8 @@ pub struct StartLaunchArgs {
     pub duration: u8,  // should be in days
     pub raise_in: TokenType,
     pub price_per_token: u64,
+    pub min_contribution: Option<u64>,
+    pub max_contribution: Option<u64>,
     pub fee_per_vbytes: Option<u64>,
 }
 
@@ -134,6 +504,7 @@ pub async fn start_launch(
         symbol,
         divisibility,
         total_supply,
+        terms,
         turbo,
         website,
         x,
@@ -145,34 +516,704 @@ pub async fn start_launch(
         duration,
         raise_in,
         price_per_token,
+        min_contribution,
+        max_contribution,
         fee_per_vbytes,
     }: StartLaunchArgs,
-) {
-    let caller = ic_cdk::caller();
+) -> SubmittedTxidType {
+    let caller = authenticated_caller();
+    // the rune id of the etching is only learned from the indexer
+    if read_config(|config| config.ord_indexer.is_none()) {
+        ic_cdk::trap("ord indexer not configured")
+    }
+    // the creator is paid out to the wallet they signed in with
+    if let Err(err) = bind_address(caller).await {
+        ic_cdk::trap(&err)
+    }
     let caller_addresses = Addresses::from(&caller);
     let caller_address = address_validation(&caller_addresses.bitcoin).unwrap();
-    let (spaced_rune, total_supply, symbol) =
-        match validate_etching(&runename, symbol, divisibility, total_supply) {
+    let (spaced_rune, premine, rune_symbol, rune_terms) =
+        match validate_etching(&runename, symbol, divisibility, total_supply, terms) {
             Err(err) => ic_cdk::trap(&err),
-            Ok((sr, total_supply, symbol)) => (sr, total_supply, symbol),
+            Ok(validated) => validated,
         };
-    let fee_per_vbytes = fee_per_vbytes.unwrap_or(20_000); // default to 20sats per vbytes
+    if hard_cap == 0 {
+        ic_cdk::trap("hard cap must be over 0")
+    }
+    if soft_cap > hard_cap {
+        ic_cdk::trap("soft cap can't be greater than hard cap")
+    }
+    if price_per_token == 0 {
+        ic_cdk::trap("price per token must be over 0")
+    }
+    if duration == 0 {
+        ic_cdk::trap("duration must be at least a day")
+    }
+    if let (Some(min), Some(max)) = (min_contribution, max_contribution) {
+        if min > max {
+            ic_cdk::trap("minimum contribution can't be greater than maximum contribution")
+        }
+    }
+    if let Err(err) = raise_in.ledger() {
+        ic_cdk::trap(&err)
+    }
+    // the premine has to cover what is sold at the hard cap
+    match allocation_for(hard_cap, price_per_token, divisibility) {
+        Err(err) => ic_cdk::trap(&err),
+        Ok(allocation) if allocation > premine => {
+            ic_cdk::trap("total supply doesn't cover the hard cap at this price")
+        }
+        Ok(_) => {}
+    }
+    let fee_per_vbytes = match fee_per_vbytes {
+        Some(fee) => fee,
+        None => get_fee_per_vbyte().await,
+    };
+    write_utxo_manager(|manager| manager.track_address(&caller_addresses.bitcoin));
+    if let Err(err) = sync_address(&caller_addresses.bitcoin).await {
+        ic_cdk::trap(&err)
+    }
+    let launch_id = write_launch_manager(|manager| manager.next_launch_id());
+    // the premine is held in the launch vault until settlement
+    let vault_address =
+        address_validation(&Addresses::for_launch(launch_id).bitcoin_legacy).unwrap();
     let arg = EtchingArgs {
+        launch_id,
         content_type,
         logo,
-        reveal_address: caller_address.clone(),
+        reveal_address: vault_address,
         spaced_rune,
-        premine: total_supply,
+        premine,
         divisibility,
-        symbol,
+        symbol: rune_symbol,
+        terms: rune_terms,
         fee_payer: caller_address.clone(),
         fee_payer_account: caller_addresses.icrc1,
         turbo,
         postage: None,
         fee_per_vbytes,
     };
+    let (commit_txn, utxos, pending_reveal, spend) = etch(arg).await;
+    if let Err(err) = send_transaction(&commit_txn).await {
+        ic_cdk::trap(&err)
+    }
+    spend.broadcast();
+    let txid = commit_txn.compute_txid().to_string();
+    write_pending_reveals(|reveals| reveals.insert(txid.clone(), pending_reveal));
+    let inputs = utxos
+        .into_iter()
+        .map(|utxo| SpentInput {
+            utxo,
+            address: caller_addresses.bitcoin.clone(),
+            account: Some(caller_addresses.icrc1),
+        })
+        .collect();
+    // the commit is the only output besides the change
+    let fee_output = (commit_txn.output.len() > 1).then_some(1);
+    register_transaction(
+        TransactionType::Etching { launch_id },
+        &commit_txn,
+        inputs,
+        fee_output,
+    );
+    schedule_reveal(txid.clone());
+
+    let created_at = ic_cdk::api::time();
+    let (starts_at, ends_at) = Launch::schedule(created_at, starts_in, duration);
+    write_launch_manager(|manager| {
+        manager.insert_launch(Launch {
+            created_by: caller,
+            launch_id,
+            runename,
+            divisibility,
+            symbol,
+            total_supply: premine,
+            terms,
+            mints: None,
+            rune_id: None,
+            website,
+            x,
+            telegram,
+            openchat,
+            hard_cap,
+            soft_cap,
+            price_per_token,
+            min_contribution,
+            max_contribution,
+            raise_in,
+            raised: 0,
+            commit_txid: txid.clone(),
+            reveal_txid: None,
+            premine_utxo: None,
+            payout_txid: None,
+            last_error: None,
+            created_at,
+            starts_at,
+            ends_at,
+            status: LaunchStatus::PendingEtching,
+        })
+    });
+    write_user_manager(|manager| manager.record_launch_created(caller, launch_id));
+
+    SubmittedTxidType::Bitcoin { txid }
+}
+
+// simulates the etching `start_launch` would broadcast for the caller with
+// these arguments, priced at the cached fee rate unless one is given
+fn quote_launch(
+    StartLaunchArgs {
+        logo,
+        content_type,
+        runename,
+        symbol,
+        divisibility,
+        total_supply,
+        terms,
+        turbo,
+        fee_per_vbytes,
+        ..
+    }: StartLaunchArgs,
+) -> EtchingQuote {
+    let caller_addresses = Addresses::from(&ic_cdk::caller());
+    let (spaced_rune, premine, rune_symbol, rune_terms) =
+        match validate_etching(&runename, symbol, divisibility, total_supply, terms) {
+            Err(err) => ic_cdk::trap(&err),
+            Ok(validated) => validated,
+        };
+    let next_launch_id = read_launch_manager(|manager| *manager.counter.get());
+    let arg = EtchingArgs {
+        launch_id: next_launch_id,
+        content_type,
+        logo,
+        reveal_address: address_validation(&Addresses::for_launch(next_launch_id).bitcoin_legacy)
+            .unwrap(),
+        spaced_rune,
+        premine,
+        divisibility,
+        symbol: rune_symbol,
+        terms: rune_terms,
+        fee_payer: address_validation(&caller_addresses.bitcoin).unwrap(),
+        fee_payer_account: caller_addresses.icrc1,
+        turbo,
+        postage: None,
+        fee_per_vbytes: fee_per_vbytes.unwrap_or_else(|| cached_fee_per_vbyte(FeePriority::Medium)),
+    };
+    match quote_etching(&arg) {
+        Err(err) => ic_cdk::trap(&err),
+        Ok(quote) => quote,
+    }
+}
+
+// commit and reveal fees `start_launch` would charge the caller
+#[query]
+pub fn estimate_launch_cost(args: StartLaunchArgs) -> EtchingCost {
+    quote_launch(args).into()
+}
+
+// the full breakdown of an etching, including what has to be deposited first
+#[query]
+pub fn quote_launch_etching(args: StartLaunchArgs) -> EtchingQuote {
+    quote_launch(args)
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct AccelerateLaunchArgs {
+    pub launch_id: u128,
+    pub fee_per_vbytes: Option<u64>,
+}
+
+// speeds up the commit of the caller's launch with a child transaction spending
+// its change, so that both together pay `fee_per_vbytes`
+#[update]
+pub async fn accelerate_launch(
+    AccelerateLaunchArgs {
+        launch_id,
+        fee_per_vbytes,
+    }: AccelerateLaunchArgs,
+) -> SubmittedTxidType {
+    let caller = authenticated_caller();
+    let launch = match read_launch_manager(|manager| manager.get_launch(launch_id)) {
+        None => ic_cdk::trap("launch doesn't exist"),
+        Some(launch) => launch,
+    };
+    if launch.created_by != caller {
+        ic_cdk::trap("only the creator can accelerate a launch")
+    }
+    if launch.status != LaunchStatus::PendingEtching || launch.reveal_txid.is_some() {
+        ic_cdk::trap("commit is already confirmed")
+    }
+    let fee_per_vbytes = match fee_per_vbytes {
+        Some(fee) => fee,
+        None => get_fee_per_vbyte().await,
+    };
+    match accelerate_transaction(&launch.commit_txid, fee_per_vbytes).await {
+        Err(err) => ic_cdk::trap(&err),
+        Ok(txid) => SubmittedTxidType::Bitcoin { txid },
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ParticipateArgs {
+    pub launch_id: u128,
+    pub amount: u64, // in sats, or the smallest unit of the raise's ledger
+    pub fee_per_vbytes: Option<u64>,
+}
+
+// pays the accepted part of `amount` from the caller's deposit address into
+// the launch escrow, anything over the remaining cap stays with the caller
+#[update]
+pub async fn participate(
+    ParticipateArgs {
+        launch_id,
+        amount,
+        fee_per_vbytes,
+    }: ParticipateArgs,
+) -> SubmittedTxidType {
+    let caller = authenticated_caller();
+    let launch = match read_launch_manager(|manager| manager.get_launch(launch_id)) {
+        None => ic_cdk::trap("launch doesn't exist"),
+        Some(launch) => launch,
+    };
+    if launch.status != LaunchStatus::Live {
+        ic_cdk::trap("launch isn't live")
+    }
+    // allocations and refunds go to the wallet the caller signed in with
+    if let Err(err) = bind_address(caller).await {
+        ic_cdk::trap(&err)
+    }
+    let ledger = match launch.raise_in.ledger() {
+        Err(err) => ic_cdk::trap(&err),
+        Ok(ledger) => ledger,
+    };
+    if let Some(ledger) = ledger {
+        let escrow = Addresses::for_launch(launch_id).icrc1;
+        let block_index = participate_on_ledger(&ledger, launch_id, caller, amount, escrow).await;
+        return SubmittedTxidType::Icrc {
+            ledger,
+            block_index,
+        };
+    }
+    let fee_per_vbytes = match fee_per_vbytes {
+        Some(fee) => fee,
+        None => get_fee_per_vbyte().await,
+    };
+    let caller_addresses = Addresses::from(&caller);
+    let caller_address = address_validation(&caller_addresses.bitcoin).unwrap();
+    let escrow_addresses = Addresses::for_launch(launch_id);
+    let escrow_address = address_validation(&escrow_addresses.bitcoin).unwrap();
+    write_utxo_manager(|manager| manager.track_address(&caller_addresses.bitcoin));
+    if let Err(err) = sync_address(&caller_addresses.bitcoin).await {
+        ic_cdk::trap(&err)
+    }
+    write_utxo_manager(|manager| manager.track_address(&escrow_addresses.bitcoin));
+
+    let accepted = match write_launch_manager(|manager| {
+        manager.reserve_contribution(launch_id, caller, amount)
+    }) {
+        Err(err) => ic_cdk::trap(&err),
+        Ok(accepted) => accepted,
+    };
+    if accepted < DUST_THRESHOLD {
+        ic_cdk::trap(&format!(
+            "contribution must be at least {} sats",
+            DUST_THRESHOLD
+        ))
+    }
+    let (txn, utxos, spend) = match build_transaction_with_fee(
+        &caller_address,
+        &escrow_address.script_pubkey(),
+        fee_per_vbytes,
+        Amount::from_sat(accepted),
+    ) {
+        Err(required) => ic_cdk::trap(&format!(
+            "insufficient balance. required: {} sats",
+            required
+        )),
+        Ok(result) => result,
+    };
+    // signing and broadcasting are awaited, the reservation and the spent marks
+    // are released by their guards when either of them fails
+    let reservation = reservation_guard(launch_id, caller, accepted);
+    let txn = ecdsa_sign_transaction(&caller_addresses.icrc1, &caller_address, txn, &utxos).await;
+    if let Err(err) = send_transaction(&txn).await {
+        ic_cdk::trap(&err)
+    }
+    spend.broadcast();
+    reservation.disarm();
+    let txid = txn.compute_txid().to_string();
+
+    let _ =
+        write_launch_manager(|manager| manager.record_deposit(launch_id, &caller, txid.clone()));
+    write_user_manager(|manager| manager.record_launch_participated(caller, launch_id));
+
+    SubmittedTxidType::Bitcoin { txid }
+}
+
+// a contribution reserved before its funds are pulled or broadcast, given back
+// unless disarmed once the funds arrived
+fn reservation_guard(
+    launch_id: u128,
+    contributor: Principal,
+    accepted: u64,
+) -> OnDrop<u64, impl FnOnce(u64)> {
+    OnDrop::new(accepted, move |amount| {
+        let _ = write_launch_manager(|manager| {
+            manager.release_contribution(launch_id, &contributor, amount)
+        });
+    })
+}
+
+// pulls the accepted part of `amount` from the caller's ledger account into
+// the launch's `escrow` account, the caller has to approve it plus the ledger
+// fee first. returns the block index of the transfer
+async fn participate_on_ledger<L: Ledger>(
+    ledger: &L,
+    launch_id: u128,
+    caller: Principal,
+    amount: u64,
+    escrow: Account,
+) -> u64 {
+    let fee = match ledger.fee().await {
+        Err(err) => ic_cdk::trap(&err),
+        Ok(fee) => fee,
+    };
+    let accepted = match write_launch_manager(|manager| {
+        manager.reserve_contribution(launch_id, caller, amount)
+    }) {
+        Err(err) => ic_cdk::trap(&err),
+        Ok(accepted) => accepted,
+    };
+    // refunds are paid less the ledger fee
+    if accepted <= fee {
+        ic_cdk::trap("contribution doesn't cover the ledger fee")
+    }
+    let reservation = reservation_guard(launch_id, caller, accepted);
+    let block_index = match ledger
+        .transfer_from(Account::from(caller), escrow, accepted)
+        .await
+    {
+        Err(err) => ic_cdk::trap(&err),
+        Ok(block_index) => block_index,
+    };
+    reservation.disarm();
+
+    let _ = write_launch_manager(|manager| {
+        manager.record_deposit(launch_id, &caller, block_index.to_string())
+    });
+    write_user_manager(|manager| manager.record_launch_participated(caller, launch_id));
+
+    block_index
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct MintArgs {
+    pub rune_id: RuneId,
+    pub fee_per_vbytes: Option<u64>,
+}
+
+// mints a rune launched through this canister into the caller's deposit address
+#[update]
+pub async fn mint(
+    MintArgs {
+        rune_id,
+        fee_per_vbytes,
+    }: MintArgs,
+) -> SubmittedTxidType {
+    let caller = authenticated_caller();
+    let launch = match read_launch_manager(|manager| manager.launch_by_rune(&rune_id)) {
+        None => ic_cdk::trap("rune isn't mintable through this canister"),
+        Some(launch) => launch,
+    };
+    let terms = match launch.terms {
+        None => ic_cdk::trap("rune has no mint terms"),
+        Some(terms) => terms,
+    };
+    let terms = match validate_terms(terms, 10u128.pow(launch.divisibility as u32)) {
+        Err(err) => ic_cdk::trap(&err),
+        Ok(terms) => terms,
+    };
+    let fee_per_vbytes = match fee_per_vbytes {
+        Some(fee) => fee,
+        None => get_fee_per_vbyte().await,
+    };
+    let caller_addresses = Addresses::from(&caller);
+    let caller_address = address_validation(&caller_addresses.bitcoin).unwrap();
+    write_utxo_manager(|manager| manager.track_address(&caller_addresses.bitcoin));
+    if let Err(err) = sync_address(&caller_addresses.bitcoin).await {
+        ic_cdk::trap(&err)
+    }
+    let indexed_mints = if read_config(|config| config.ord_indexer.is_some()) {
+        match get_rune_mints(rune_id).await {
+            Err(err) => ic_cdk::trap(&err),
+            Ok(mints) => mints.unwrap_or(0),
+        }
+    } else {
+        0
+    };
+
+    // the mint confirms in the next block at the earliest
+    let height = read_utxo_manager(|manager| manager.sync_status(&caller_addresses.bitcoin))
+        .map_or(0, |status| status.tip_height as u64)
+        + 1;
+    let minted = read_launch_manager(|manager| manager.get_launch(launch.launch_id))
+        .and_then(|launch| launch.mints)
+        .unwrap_or(0)
+        .max(indexed_mints);
+    let amount = match validate_mint(&terms, rune_id.block as u64, height, minted) {
+        Err(err) => ic_cdk::trap(&err),
+        Ok(amount) => amount,
+    };
+
+    let postage = caller_address.script_pubkey().minimal_non_dust();
+    let output = vec![
+        TxOut {
+            script_pubkey: caller_address.script_pubkey(),
+            value: postage,
+        },
+        TxOut {
+            script_pubkey: mint_runestone(rune_id.into()),
+            value: Amount::ZERO,
+        },
+    ];
+    let (txn, utxos, spend) =
+        match build_transaction_with_outputs(&caller_address, output, fee_per_vbytes) {
+            Err(required) => ic_cdk::trap(&format!(
+                "insufficient balance for minting. required: {} sats",
+                required
+            )),
+            Ok(result) => result,
+        };
+    // counted before the awaits so concurrent mints see it, and given back
+    // unless the mint is broadcast
+    let _ = write_launch_manager(|manager| {
+        manager.update_launch(launch.launch_id, |launch| launch.mints.replace(minted + 1))
+    });
+    let mint_slot = OnDrop::new(launch.launch_id, |launch_id| {
+        let _ = write_launch_manager(|manager| {
+            manager.update_launch(launch_id, |launch| {
+                launch.mints = launch.mints.map(|mints| mints.saturating_sub(1))
+            })
+        });
+    });
+    let txn = ecdsa_sign_transaction(&caller_addresses.icrc1, &caller_address, txn, &utxos).await;
+    if let Err(err) = send_transaction(&txn).await {
+        ic_cdk::trap(&err)
+    }
+    spend.broadcast();
+    mint_slot.disarm();
+
+    let allocation = index_transaction(&txn, HashMap::new(), None, |id| {
+        (*id == rune_id).then_some(amount)
+    });
+    record_runic_outputs(&txn, &allocation);
+
+    SubmittedTxidType::Bitcoin {
+        txid: txn.compute_txid().to_string(),
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct WithdrawBtcArgs {
+    pub to: String,
+    // in sats, `None` sends the whole balance minus the fee
+    pub amount: Option<u64>,
+    pub fee_per_vbytes: Option<u64>,
+    // spends the p2tr deposit address instead of the p2wpkh one
+    pub from_taproot: Option<bool>,
+}
+
+// sends bitcoin from the caller's deposit address. inputs signal RBF so a
+// stuck withdrawal can be bumped
+#[update]
+pub async fn withdraw_btc(
+    WithdrawBtcArgs {
+        to,
+        amount,
+        fee_per_vbytes,
+        from_taproot,
+    }: WithdrawBtcArgs,
+) -> SubmittedTxidType {
+    let caller = authenticated_caller();
+    let to = match address_validation(&to) {
+        Err(err) => ic_cdk::trap(&err),
+        Ok(addr) => addr,
+    };
+    let fee_per_vbytes = match fee_per_vbytes {
+        Some(fee) => fee,
+        None => get_fee_per_vbyte().await,
+    };
+    let caller_addresses = Addresses::from(&caller);
+    let from = match from_taproot {
+        Some(true) => caller_addresses.bitcoin_taproot,
+        _ => caller_addresses.bitcoin,
+    };
+    let caller_address = address_validation(&from).unwrap();
+    write_utxo_manager(|manager| manager.track_address(&from));
+    if let Err(err) = sync_address(&from).await {
+        ic_cdk::trap(&err)
+    }
+
+    let account = caller_addresses.icrc1;
+    let txn = match amount {
+        None => transfer_all(&account, &caller_address, &to, fee_per_vbytes).await,
+        Some(amount) => transfer(&account, &caller_address, &to, amount, fee_per_vbytes).await,
+    };
+    let (txn, spend) = match txn {
+        Err(err) => ic_cdk::trap(&err),
+        Ok(result) => result,
+    };
+    if let Err(err) = send_transaction(&txn).await {
+        ic_cdk::trap(&err)
+    }
+    spend.broadcast();
+
+    SubmittedTxidType::Bitcoin {
+        txid: txn.compute_txid().to_string(),
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct WithdrawRunesArgs {
+    pub rune_id: RuneId,
+    pub amount: u128,
+    pub to: String,
+    pub fee_per_vbytes: Option<u64>,
+}
+
+// sends runes from the caller's deposit address, fees are paid from its bitcoin
+#[update]
+pub async fn withdraw_runes(
+    WithdrawRunesArgs {
+        rune_id,
+        amount,
+        to,
+        fee_per_vbytes,
+    }: WithdrawRunesArgs,
+) -> SubmittedTxidType {
+    let caller = authenticated_caller();
+    let to = match address_validation(&to) {
+        Err(err) => ic_cdk::trap(&err),
+        Ok(addr) => addr,
+    };
+    let fee_per_vbytes = match fee_per_vbytes {
+        Some(fee) => fee,
+        None => get_fee_per_vbyte().await,
+    };
+    let caller_addresses = Addresses::from(&caller);
+    let caller_address = address_validation(&caller_addresses.bitcoin).unwrap();
+    write_utxo_manager(|manager| manager.track_address(&caller_addresses.bitcoin));
+    if let Err(err) = sync_address(&caller_addresses.bitcoin).await {
+        ic_cdk::trap(&err)
+    }
+
+    let (txn, allocation, spend) = match transfer_runes(
+        &caller_addresses.icrc1,
+        &caller_address,
+        &to,
+        rune_id,
+        amount,
+        fee_per_vbytes,
+    )
+    .await
+    {
+        Err(err) => ic_cdk::trap(&err),
+        Ok(result) => result,
+    };
+    if let Err(err) = send_transaction(&txn).await {
+        ic_cdk::trap(&err)
+    }
+    spend.broadcast();
+    record_runic_outputs(&txn, &allocation);
+
+    SubmittedTxidType::Bitcoin {
+        txid: txn.compute_txid().to_string(),
+    }
 }
 
-pub fn participate() {}
+#[cfg(test)]
+mod tests {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    use super::*;
+    use crate::test_support::{block_on, contributor, live_launch, StandInLedger};
+
+    #[test]
+    fn participating_on_a_ledger_records_the_deposit() {
+        write_launch_manager(|manager| manager.insert_launch(live_launch(1, TokenType::Icp)));
+        let ledger = StandInLedger::new(10);
+        ledger.mint(Account::from(contributor(2)), 2_000);
+
+        let block_index = block_on(participate_on_ledger(
+            &ledger,
+            1,
+            contributor(2),
+            1_000,
+            ledger.escrow(),
+        ));
+
+        assert_eq!(ledger.balance(ledger.escrow()), 1_000);
+        let contribution =
+            read_launch_manager(|manager| manager.get_contribution(1, &contributor(2))).unwrap();
+        assert_eq!(contribution.deposits, vec![block_index.to_string()]);
+    }
+
+    #[test]
+    fn upgrade_settings_keep_what_they_dont_set() {
+        let receiver = Principal::from_slice(&[4; 10]);
+        apply_settings(UpgradeArgs {
+            commission_receiver: Some(receiver),
+            commission_bps: Some(250),
+            ..Default::default()
+        })
+        .unwrap();
+
+        apply_settings(UpgradeArgs {
+            rbf_timeout_secs: Some(600),
+            ..Default::default()
+        })
+        .unwrap();
+
+        read_config(|config| {
+            assert_eq!(config.commission_receiver, Some(receiver));
+            assert_eq!(config.commission_bps(), 250);
+            assert_eq!(config.rbf_timeout_secs(), 600);
+        });
+    }
+
+    #[test]
+    fn invalid_upgrade_settings_change_nothing() {
+        let result = apply_settings(UpgradeArgs {
+            commission_bps: Some(10_001),
+            rbf_timeout_secs: Some(600),
+            ..Default::default()
+        });
+
+        assert!(result.is_err());
+        read_config(|config| {
+            assert!(config.commission_bps.is_none());
+            assert!(config.rbf_timeout_secs.is_none());
+        });
+    }
+
+    #[test]
+    fn failed_transfer_from_releases_the_reservation() {
+        write_launch_manager(|manager| manager.insert_launch(live_launch(1, TokenType::Icp)));
+        let ledger = StandInLedger::new(10);
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            block_on(participate_on_ledger(
+                &ledger,
+                1,
+                contributor(2),
+                1_000,
+                ledger.escrow(),
+            ))
+        }));
+
+        assert!(result.is_err());
+        let launch = read_launch_manager(|manager| manager.get_launch(1)).unwrap();
+        assert_eq!(launch.raised, 0);
+    }
+}
 
 ic_cdk::export_candid!();
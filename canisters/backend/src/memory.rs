@@ -1,14 +1,42 @@
+use candid::{CandidType, Decode, Encode};
 use ic_stable_structures::{
     memory_manager::{MemoryId, VirtualMemory},
     DefaultMemoryImpl,
 };
+use serde::de::DeserializeOwned;
 
 pub type Memory = VirtualMemory<DefaultMemoryImpl>;
 
+pub fn encode<T: CandidType>(value: &T) -> Vec<u8> {
+    Encode!(value).expect("should encode")
+}
+
+// stored records only ever gain `Option` fields, which candid decodes as `None`
+// from older encodings and extra fields are skipped. anything else is a layout
+// change that needs a migration, so the trap names the type
+pub fn decode<T: CandidType + DeserializeOwned>(bytes: &[u8]) -> T {
+    match Decode!(bytes, T) {
+        Err(err) => ic_cdk::trap(&format!(
+            "failed to decode {}: {}",
+            std::any::type_name::<T>(),
+            err
+        )),
+        Ok(value) => value,
+    }
+}
+
 pub enum MemoryIds {
     Config,
     Runic,
     Bitcoin,
+    Reveal,
+    Tracked,
+    Spent,
+    Launches,
+    LaunchCounter,
+    Contributions,
+    Users,
+    Submitted,
 }
 
 impl From<MemoryIds> for MemoryId {
@@ -17,6 +45,14 @@ impl From<MemoryIds> for MemoryId {
             MemoryIds::Config => 1,
             MemoryIds::Runic => 2,
             MemoryIds::Bitcoin => 3,
+            MemoryIds::Reveal => 4,
+            MemoryIds::Tracked => 5,
+            MemoryIds::Spent => 6,
+            MemoryIds::Launches => 7,
+            MemoryIds::LaunchCounter => 8,
+            MemoryIds::Contributions => 9,
+            MemoryIds::Users => 10,
+            MemoryIds::Submitted => 11,
         };
         MemoryId::new(id)
     }
@@ -2,10 +2,17 @@ use std::cell::RefCell;
 
 use config::{init_stable_config, Config, StableConfig};
 use ic_stable_structures::{memory_manager::MemoryManager, DefaultMemoryImpl};
+use launch_manager::LaunchManager;
+use reveal_manager::{init_reveal_mapping, RevealMapping};
+use txn_manager::{init_submitted_mapping, SubmittedMapping};
+use user_manager::UserManager;
 use utxo_manager::UtxoManager;
 
 pub mod config;
 pub mod launch_manager;
+pub mod migrations;
+pub mod reveal_manager;
+pub mod txn_manager;
 pub mod user_manager;
 pub mod utxo_manager;
 
@@ -14,6 +21,10 @@ thread_local! {
         RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
     pub static CONFIG: RefCell<StableConfig> = RefCell::new(init_stable_config());
     pub static UTXO_MANAGER: RefCell<UtxoManager> = RefCell::default();
+    pub static PENDING_REVEALS: RefCell<RevealMapping> = RefCell::new(init_reveal_mapping());
+    pub static LAUNCH_MANAGER: RefCell<LaunchManager> = RefCell::default();
+    pub static USER_MANAGER: RefCell<UserManager> = RefCell::default();
+    pub static SUBMITTED_TXNS: RefCell<SubmittedMapping> = RefCell::new(init_submitted_mapping());
 }
 
 // helper functions
@@ -52,3 +63,59 @@ where
 {
     UTXO_MANAGER.with_borrow_mut(|manager| f(manager))
 }
+
+pub fn read_pending_reveals<F, R>(f: F) -> R
+where
+    F: FnOnce(&RevealMapping) -> R,
+{
+    PENDING_REVEALS.with_borrow(|reveals| f(reveals))
+}
+
+pub fn write_pending_reveals<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut RevealMapping) -> R,
+{
+    PENDING_REVEALS.with_borrow_mut(|reveals| f(reveals))
+}
+
+pub fn read_launch_manager<F, R>(f: F) -> R
+where
+    F: FnOnce(&LaunchManager) -> R,
+{
+    LAUNCH_MANAGER.with_borrow(|manager| f(manager))
+}
+
+pub fn write_launch_manager<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut LaunchManager) -> R,
+{
+    LAUNCH_MANAGER.with_borrow_mut(|manager| f(manager))
+}
+
+pub fn read_user_manager<F, R>(f: F) -> R
+where
+    F: FnOnce(&UserManager) -> R,
+{
+    USER_MANAGER.with_borrow(|manager| f(manager))
+}
+
+pub fn write_user_manager<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut UserManager) -> R,
+{
+    USER_MANAGER.with_borrow_mut(|manager| f(manager))
+}
+
+pub fn read_submitted_txns<F, R>(f: F) -> R
+where
+    F: FnOnce(&SubmittedMapping) -> R,
+{
+    SUBMITTED_TXNS.with_borrow(|txns| f(txns))
+}
+
+pub fn write_submitted_txns<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut SubmittedMapping) -> R,
+{
+    SUBMITTED_TXNS.with_borrow_mut(|txns| f(txns))
+}
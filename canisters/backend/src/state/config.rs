@@ -1,5 +1,9 @@
-use crate::{memory::MemoryIds, EcdsaPublicKey, Memory, SchnorrPublicKey};
-use candid::{CandidType, Decode, Encode, Principal};
+use crate::{
+    chain::btc::runestone::MAX_STANDARD_OP_RETURN_SIZE,
+    memory::{decode, encode, MemoryIds},
+    EcdsaPublicKey, Memory, SchnorrPublicKey,
+};
+use candid::{CandidType, Principal};
 use ic_cdk::api::management_canister::{
     bitcoin::BitcoinNetwork,
     ecdsa::{EcdsaCurve, EcdsaKeyId},
@@ -10,6 +14,83 @@ use serde::Deserialize;
 
 use super::read_memory_manager;
 
+const DEFAULT_RBF_TIMEOUT_SECS: u64 = 3 * 60 * 60;
+const ICP_LEDGER: &str = "ryjl3-tyaaa-aaaaa-aaaba-cai";
+const CKBTC_LEDGER: &str = "mxzaz-hqaaa-aaaar-qaada-cai";
+const CKTESTBTC_LEDGER: &str = "mc6ru-gyaaa-aaaar-qaaaq-cai";
+
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub enum FeePriority {
+    Low,
+    Medium,
+    High,
+}
+
+// how fee rates are picked from the bitcoin canister's fee percentiles. all
+// rates are in millisatoshis per vbyte
+#[derive(CandidType, Deserialize, Clone)]
+pub struct FeePolicy {
+    pub low_percentile: u8,
+    pub medium_percentile: u8,
+    pub high_percentile: u8,
+    pub min_fee_per_vbytes: u64,
+    pub max_fee_per_vbytes: u64,
+    // regtest has no fee percentiles, it's also used until any are known
+    pub default_fee_per_vbytes: u64,
+}
+
+impl Default for FeePolicy {
+    fn default() -> Self {
+        Self {
+            low_percentile: 25,
+            medium_percentile: 50,
+            high_percentile: 75,
+            min_fee_per_vbytes: 1_000,
+            max_fee_per_vbytes: 500_000,
+            default_fee_per_vbytes: 2_000,
+        }
+    }
+}
+
+impl FeePolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        let percentiles = [
+            self.low_percentile,
+            self.medium_percentile,
+            self.high_percentile,
+        ];
+        if percentiles.iter().any(|percentile| *percentile > 100) {
+            return Err(String::from("percentiles can't be over 100"));
+        }
+        if self.min_fee_per_vbytes > self.max_fee_per_vbytes {
+            return Err(String::from(
+                "minimum fee rate can't be greater than maximum",
+            ));
+        }
+        if !(self.min_fee_per_vbytes..=self.max_fee_per_vbytes)
+            .contains(&self.default_fee_per_vbytes)
+        {
+            return Err(String::from(
+                "default fee rate has to be between the minimum and maximum",
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn fee_per_vbytes(&self, percentiles: &[u64], priority: FeePriority) -> u64 {
+        let percentile = match priority {
+            FeePriority::Low => self.low_percentile,
+            FeePriority::Medium => self.medium_percentile,
+            FeePriority::High => self.high_percentile,
+        } as usize;
+        let fee_per_vbytes = match percentiles.last() {
+            None => self.default_fee_per_vbytes,
+            Some(last) => percentiles.get(percentile).copied().unwrap_or(*last),
+        };
+        fee_per_vbytes.clamp(self.min_fee_per_vbytes, self.max_fee_per_vbytes)
+    }
+}
+
 #[derive(CandidType, Deserialize, Default, Clone)]
 pub struct Config {
     pub auth: Option<Principal>,
@@ -17,15 +98,32 @@ pub struct Config {
     pub ecdsa_public_key: Option<EcdsaPublicKey>,
     pub schnorr_public_key: Option<SchnorrPublicKey>,
     pub keyname: Option<String>,
+    pub ord_indexer: Option<Principal>,
+    pub commission_receiver: Option<Principal>,
+    // share of the raised funds taken as commission, in basis points
+    pub commission_bps: Option<u16>,
+    // bytes a distribution runestone may take, nodes relaying larger OP_RETURN
+    // outputs allow more edicts per transaction
+    pub runestone_size_limit: Option<u32>,
+    // seconds a transaction may stay unconfirmed before its fee is bumped
+    pub rbf_timeout_secs: Option<u64>,
+    pub fee_policy: Option<FeePolicy>,
+    // ledgers of raises in icp and ckbtc, the mainnet ones unless set
+    pub icp_ledger: Option<Principal>,
+    pub ckbtc_ledger: Option<Principal>,
+    // sign-in with bitcoin provider binding callers to their wallet address
+    pub siwb_provider: Option<Principal>,
+    // layout version of the stable state, `None` before versioning
+    pub schema_version: Option<u32>,
 }
 
 impl Storable for Config {
     fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+        std::borrow::Cow::Owned(encode(self))
     }
 
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).expect("should decode")
+        decode(bytes.as_ref())
     }
 
     const BOUND: Bound = Bound::Unbounded;
@@ -63,6 +161,46 @@ impl Config {
         }
     }
 
+    pub fn commission_bps(&self) -> u16 {
+        self.commission_bps.unwrap_or(0)
+    }
+
+    pub fn schema_version(&self) -> u32 {
+        self.schema_version.unwrap_or(0)
+    }
+
+    pub fn runestone_size_limit(&self) -> usize {
+        self.runestone_size_limit
+            .map_or(MAX_STANDARD_OP_RETURN_SIZE, |limit| limit as usize)
+    }
+
+    pub fn rbf_timeout_secs(&self) -> u64 {
+        self.rbf_timeout_secs.unwrap_or(DEFAULT_RBF_TIMEOUT_SECS)
+    }
+
+    pub fn fee_policy(&self) -> FeePolicy {
+        self.fee_policy.clone().unwrap_or_default()
+    }
+
+    pub fn icp_ledger(&self) -> Principal {
+        self.icp_ledger
+            .unwrap_or_else(|| Principal::from_text(ICP_LEDGER).unwrap())
+    }
+
+    // regtest has no ckbtc ledger unless one is set with `ckbtc_ledger`
+    pub fn ckbtc_ledger(&self) -> Result<Principal, String> {
+        if let Some(ledger) = self.ckbtc_ledger {
+            return Ok(ledger);
+        }
+        match self.bitcoin_network() {
+            BitcoinNetwork::Mainnet => Ok(Principal::from_text(CKBTC_LEDGER).unwrap()),
+            BitcoinNetwork::Testnet => Ok(Principal::from_text(CKTESTBTC_LEDGER).unwrap()),
+            BitcoinNetwork::Regtest => Err(String::from(
+                "ckbtc ledger isn't configured, set it with the ckbtc_ledger setting",
+            )),
+        }
+    }
+
     pub fn ecdsakeyid(&self) -> EcdsaKeyId {
         let name = self.keyname();
         EcdsaKeyId {
@@ -85,7 +223,73 @@ pub type StableConfig = StableCell<Config, Memory>;
 pub fn init_stable_config() -> StableConfig {
     read_memory_manager(|manager| {
         let memory = manager.get(MemoryIds::Config.into());
-        StableConfig::new(memory, Config::default())
+        // keeps what an earlier version stored
+        StableConfig::init(memory, Config::default())
             .expect("failed to initialize memory for config")
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use candid::Encode;
+
+    use super::*;
+    use crate::state::migrations::SCHEMA_VERSION;
+
+    // the config as stored before any of the optional fields were added
+    #[derive(CandidType)]
+    struct PreviousConfig {
+        auth: Option<Principal>,
+        bitcoin_network: Option<BitcoinNetwork>,
+        ecdsa_public_key: Option<EcdsaPublicKey>,
+        schnorr_public_key: Option<SchnorrPublicKey>,
+        keyname: Option<String>,
+    }
+
+    #[test]
+    fn decodes_previous_layout() {
+        let auth = Principal::from_text(ICP_LEDGER).unwrap();
+        let snapshot = Encode!(&PreviousConfig {
+            auth: Some(auth),
+            bitcoin_network: Some(BitcoinNetwork::Testnet),
+            ecdsa_public_key: None,
+            schnorr_public_key: None,
+            keyname: Some(String::from("test_key_1")),
+        })
+        .unwrap();
+
+        let config = Config::from_bytes(std::borrow::Cow::Owned(snapshot));
+        assert_eq!(config.auth, Some(auth));
+        assert_eq!(config.keyname.as_deref(), Some("test_key_1"));
+        assert!(matches!(
+            config.bitcoin_network,
+            Some(BitcoinNetwork::Testnet)
+        ));
+        assert!(config.fee_policy.is_none());
+        assert!(config.siwb_provider.is_none());
+        assert_eq!(config.schema_version(), 0);
+        assert_eq!(config.rbf_timeout_secs(), DEFAULT_RBF_TIMEOUT_SECS);
+    }
+
+    #[test]
+    fn roundtrips_current_layout() {
+        let config = Config {
+            commission_bps: Some(250),
+            schema_version: Some(SCHEMA_VERSION),
+            ..Default::default()
+        };
+        let decoded = Config::from_bytes(config.to_bytes());
+        assert_eq!(decoded.commission_bps(), 250);
+        assert_eq!(decoded.schema_version(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn default_fee_rate_has_to_be_within_bounds() {
+        assert!(FeePolicy::default().validate().is_ok());
+        let policy = FeePolicy {
+            default_fee_per_vbytes: 500,
+            ..Default::default()
+        };
+        assert!(policy.validate().is_err());
+    }
+}
@@ -1,10 +1,616 @@
+use std::collections::HashMap;
+
 use candid::{CandidType, Principal};
-use serde::Deserialize;
+use ic_cdk::api::management_canister::bitcoin::Utxo;
+use ic_stable_structures::{storable::Bound, StableBTreeMap, StableCell, Storable};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chain::btc::runestone::MintTerms,
+    memory::{decode, encode, MemoryIds},
+    token_type::TokenType,
+    updater::RuneId,
+    Memory,
+};
+
+use super::read_memory_manager;
+
+const DAY_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LaunchStatus {
+    PendingEtching,
+    Etched,
+    Upcoming,
+    Live,
+    Succeeded,
+    Failed,
+    Distributed,
+    Refunded,
+}
 
-#[derive(CandidType, Deserialize)]
+impl LaunchStatus {
+    pub fn can_transition_to(self, next: LaunchStatus) -> bool {
+        use LaunchStatus::*;
+        matches!(
+            (self, next),
+            (PendingEtching, Etched)
+                | (Etched, Upcoming)
+                | (Upcoming, Live)
+                | (Live, Succeeded)
+                | (Live, Failed)
+                | (Succeeded, Distributed)
+                | (Failed, Refunded)
+        )
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone)]
 pub struct Launch {
     pub created_by: Principal,
     pub launch_id: u128,
     pub runename: String,
     pub divisibility: u8,
+    pub symbol: Option<u32>,
+    pub total_supply: u128,
+    pub terms: Option<MintTerms>,
+    // mints made through this canister
+    pub mints: Option<u128>,
+    pub rune_id: Option<RuneId>,
+    pub website: Option<String>,
+    pub x: Option<String>,
+    pub telegram: Option<String>,
+    pub openchat: Option<String>,
+    pub hard_cap: u64,
+    pub soft_cap: u64,
+    pub price_per_token: u64,
+    pub min_contribution: Option<u64>,
+    pub max_contribution: Option<u64>,
+    pub raise_in: TokenType,
+    pub raised: u64,
+    pub commit_txid: String,
+    pub reveal_txid: Option<String>,
+    // output of the launch vault holding the undistributed premine
+    pub premine_utxo: Option<Utxo>,
+    pub payout_txid: Option<String>,
+    // why the last etching check or settlement step failed, cleared once one
+    // goes through
+    pub last_error: Option<String>,
+    pub created_at: u64,
+    pub starts_at: u64,
+    pub ends_at: u64,
+    pub status: LaunchStatus,
+}
+
+impl Storable for Launch {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(encode(self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        decode(bytes.as_ref())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Launch {
+    // `starts_in` and `duration` are in days
+    pub fn schedule(created_at: u64, starts_in: u8, duration: u8) -> (u64, u64) {
+        let starts_at = created_at + starts_in as u64 * DAY_NANOS;
+        let ends_at = starts_at + duration as u64 * DAY_NANOS;
+        (starts_at, ends_at)
+    }
+
+    // the status the launch should be in at `now`, as far as time is concerned
+    pub fn next_status(&self, now: u64) -> Option<LaunchStatus> {
+        match self.status {
+            LaunchStatus::Etched => Some(LaunchStatus::Upcoming),
+            LaunchStatus::Upcoming if now >= self.starts_at => Some(LaunchStatus::Live),
+            LaunchStatus::Live if now >= self.ends_at => {
+                if self.raised >= self.soft_cap {
+                    Some(LaunchStatus::Succeeded)
+                } else {
+                    Some(LaunchStatus::Failed)
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // base units of the rune bought by `amount`, `price_per_token` being the
+    // price of a whole token
+    pub fn allocation_for(&self, amount: u64) -> Result<u128, String> {
+        allocation_for(amount, self.price_per_token, self.divisibility)
+    }
+}
+
+// the smallest amount buying a whole base unit of the rune, contributions are
+// taken in multiples of it so none of them is lost to rounding
+pub fn contribution_step(price_per_token: u64, divisibility: u8) -> u64 {
+    let (mut a, mut b) = (price_per_token as u128, 10u128.pow(divisibility as u32));
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    (price_per_token as u128 / a).max(1) as u64
+}
+
+pub fn allocation_for(amount: u64, price_per_token: u64, divisibility: u8) -> Result<u128, String> {
+    if price_per_token == 0 {
+        return Err(String::from("price per token must be over 0"));
+    }
+    10u128
+        .checked_pow(divisibility as u32)
+        .and_then(|unit| unit.checked_mul(amount as u128))
+        .map(|amount| amount / price_per_token as u128)
+        .ok_or_else(|| String::from("allocation overflows"))
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub enum LaunchSort {
+    StartTime,
+    Raised,
+    PercentOfHardCap,
+}
+
+impl LaunchSort {
+    fn key(self, launch: &Launch) -> u128 {
+        match self {
+            LaunchSort::StartTime => launch.starts_at as u128,
+            LaunchSort::Raised => launch.raised as u128,
+            // in basis points
+            LaunchSort::PercentOfHardCap => {
+                launch.raised as u128 * 10_000 / launch.hard_cap.max(1) as u128
+            }
+        }
+    }
+}
+
+// the sort key and id of the last launch on a page, the next page starts
+// right after it. launches added or moving between calls don't shift the
+// pages like an offset would
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct LaunchCursor {
+    pub key: u128,
+    pub launch_id: u128,
+}
+
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct LaunchFilter {
+    pub status: Option<LaunchStatus>,
+    pub raise_in: Option<TokenType>,
+    pub created_by: Option<Principal>,
+}
+
+impl LaunchFilter {
+    pub fn matches(&self, launch: &Launch) -> bool {
+        self.status.map_or(true, |status| launch.status == status)
+            && self.raise_in.map_or(true, |token| launch.raise_in == token)
+            && self
+                .created_by
+                .map_or(true, |creator| launch.created_by == creator)
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct Contribution {
+    pub contributor: Principal,
+    pub amount: u64,
+    pub allocation: u128,
+    // transactions paying the contribution into the launch escrow
+    pub deposits: Vec<String>,
+    pub refund_txid: Option<String>,
+    pub distribution_txid: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct LaunchContributions(pub HashMap<Principal, Contribution>);
+
+impl Storable for LaunchContributions {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(encode(self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        decode(bytes.as_ref())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+pub type LaunchMapping = StableBTreeMap<u128, Launch, Memory>;
+
+pub fn init_launch_mapping() -> LaunchMapping {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::Launches.into());
+        LaunchMapping::init(memory)
+    })
+}
+
+pub type ContributionMapping = StableBTreeMap<u128, LaunchContributions, Memory>;
+
+pub fn init_contribution_mapping() -> ContributionMapping {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::Contributions.into());
+        ContributionMapping::init(memory)
+    })
+}
+
+pub type LaunchCounter = StableCell<u128, Memory>;
+
+pub fn init_launch_counter() -> LaunchCounter {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::LaunchCounter.into());
+        LaunchCounter::init(memory, 0).expect("failed to initialize memory for launch counter")
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LaunchManager {
+    #[serde(skip, default = "init_launch_mapping")]
+    pub launches: LaunchMapping,
+    #[serde(skip, default = "init_launch_counter")]
+    pub counter: LaunchCounter,
+    #[serde(skip, default = "init_contribution_mapping")]
+    pub contributions: ContributionMapping,
+}
+
+impl Default for LaunchManager {
+    fn default() -> Self {
+        Self {
+            launches: init_launch_mapping(),
+            counter: init_launch_counter(),
+            contributions: init_contribution_mapping(),
+        }
+    }
+}
+
+impl LaunchManager {
+    pub fn next_launch_id(&mut self) -> u128 {
+        let launch_id = *self.counter.get();
+        self.counter
+            .set(launch_id + 1)
+            .expect("failed to set launch counter");
+        launch_id
+    }
+
+    pub fn insert_launch(&mut self, launch: Launch) {
+        self.launches.insert(launch.launch_id, launch);
+    }
+
+    pub fn get_launch(&self, launch_id: u128) -> Option<Launch> {
+        self.launches.get(&launch_id)
+    }
+
+    pub fn launch_by_rune(&self, rune_id: &RuneId) -> Option<Launch> {
+        self.launches
+            .iter()
+            .map(|(_, launch)| launch)
+            .find(|launch| launch.rune_id.as_ref() == Some(rune_id))
+    }
+
+    pub fn launches_with_status(&self, status: LaunchStatus) -> Vec<Launch> {
+        self.launches
+            .iter()
+            .filter(|(_, launch)| launch.status == status)
+            .map(|(_, launch)| launch)
+            .collect()
+    }
+
+    // `limit` launches matching `filter` after the `cursor`, along with the
+    // cursor of the next page. ties are ordered by launch id so every launch
+    // has a single place
+    pub fn page_launches(
+        &self,
+        filter: &LaunchFilter,
+        sort: LaunchSort,
+        descending: bool,
+        cursor: Option<LaunchCursor>,
+        limit: usize,
+    ) -> (Vec<Launch>, Option<LaunchCursor>) {
+        let position = |launch: &Launch| LaunchCursor {
+            key: sort.key(launch),
+            launch_id: launch.launch_id,
+        };
+        let mut launches: Vec<(LaunchCursor, Launch)> = self
+            .launches
+            .iter()
+            .map(|(_, launch)| (position(&launch), launch))
+            .filter(|(_, launch)| filter.matches(launch))
+            .filter(|(at, _)| match cursor {
+                None => true,
+                Some(cursor) if descending => *at < cursor,
+                Some(cursor) => *at > cursor,
+            })
+            .collect();
+        launches.sort_by_key(|(at, _)| *at);
+        if descending {
+            launches.reverse();
+        }
+        let next_cursor = (launches.len() > limit).then(|| launches[limit - 1].0);
+        let page = launches
+            .into_iter()
+            .take(limit)
+            .map(|(_, launch)| launch)
+            .collect();
+        (page, next_cursor)
+    }
+
+    pub fn update_launch<F, R>(&mut self, launch_id: u128, f: F) -> Result<R, String>
+    where
+        F: FnOnce(&mut Launch) -> R,
+    {
+        let mut launch = self
+            .launches
+            .get(&launch_id)
+            .ok_or_else(|| format!("launch {} doesn't exist", launch_id))?;
+        let result = f(&mut launch);
+        self.launches.insert(launch_id, launch);
+        Ok(result)
+    }
+
+    pub fn record_result(&mut self, launch_id: u128, result: &Result<(), String>) {
+        let _ = self.update_launch(launch_id, |launch| {
+            launch.last_error = result.as_ref().err().cloned()
+        });
+    }
+
+    pub fn transition(&mut self, launch_id: u128, status: LaunchStatus) -> Result<(), String> {
+        self.update_launch(launch_id, |launch| {
+            if !launch.status.can_transition_to(status) {
+                return Err(format!(
+                    "launch {} can't move from {:?} to {:?}",
+                    launch_id, launch.status, status
+                ));
+            }
+            launch.status = status;
+            Ok(())
+        })?
+    }
+
+    pub fn get_contributions(&self, launch_id: u128) -> LaunchContributions {
+        self.contributions.get(&launch_id).unwrap_or_default()
+    }
+
+    pub fn get_contribution(
+        &self,
+        launch_id: u128,
+        contributor: &Principal,
+    ) -> Option<Contribution> {
+        self.get_contributions(launch_id).0.remove(contributor)
+    }
+
+    // books as much of `amount` as the hard cap and the contributor's limits
+    // allow, returning the accepted amount
+    pub fn reserve_contribution(
+        &mut self,
+        launch_id: u128,
+        contributor: Principal,
+        amount: u64,
+    ) -> Result<u64, String> {
+        let launch = self
+            .get_launch(launch_id)
+            .ok_or_else(|| format!("launch {} doesn't exist", launch_id))?;
+        if launch.status != LaunchStatus::Live {
+            return Err(format!("launch {} isn't live", launch_id));
+        }
+        let mut contributions = self.get_contributions(launch_id);
+        let previous = contributions
+            .0
+            .get(&contributor)
+            .map(|contribution| contribution.amount)
+            .unwrap_or(0);
+
+        let remaining_cap = launch.hard_cap.saturating_sub(launch.raised);
+        if remaining_cap == 0 {
+            return Err(String::from("launch has reached its hard cap"));
+        }
+        let remaining_limit = launch
+            .max_contribution
+            .map_or(u64::MAX, |max| max.saturating_sub(previous));
+        if remaining_limit == 0 {
+            return Err(String::from("maximum contribution reached"));
+        }
+        // the part that doesn't buy a whole base unit stays with the contributor
+        let step = contribution_step(launch.price_per_token, launch.divisibility);
+        let accepted = amount.min(remaining_cap).min(remaining_limit);
+        let accepted = accepted - accepted % step;
+        let unit = launch.raise_in.unit();
+        if accepted == 0 {
+            return Err(format!(
+                "contribution must be a multiple of {} {}",
+                step, unit
+            ));
+        }
+        // whatever is left of the hard cap is taken even below the minimum
+        if let Some(min) = launch.min_contribution {
+            if previous + accepted < min && accepted < remaining_cap {
+                return Err(format!("contribution must be at least {} {}", min, unit));
+            }
+        }
+
+        let contribution = contributions
+            .0
+            .entry(contributor)
+            .or_insert_with(|| Contribution {
+                contributor,
+                amount: 0,
+                allocation: 0,
+                deposits: vec![],
+                refund_txid: None,
+                distribution_txid: None,
+            });
+        contribution.amount += accepted;
+        contribution.allocation = launch.allocation_for(contribution.amount)?;
+        self.contributions.insert(launch_id, contributions);
+        self.update_launch(launch_id, |launch| launch.raised += accepted)?;
+        Ok(accepted)
+    }
+
+    // undoes a reservation whose funds never arrived
+    pub fn release_contribution(
+        &mut self,
+        launch_id: u128,
+        contributor: &Principal,
+        amount: u64,
+    ) -> Result<(), String> {
+        let launch = self
+            .get_launch(launch_id)
+            .ok_or_else(|| format!("launch {} doesn't exist", launch_id))?;
+        let mut contributions = self.get_contributions(launch_id);
+        let contribution = contributions
+            .0
+            .get_mut(contributor)
+            .ok_or_else(|| String::from("contribution doesn't exist"))?;
+        contribution.amount = contribution.amount.saturating_sub(amount);
+        contribution.allocation = launch.allocation_for(contribution.amount)?;
+        if contribution.amount == 0 && contribution.deposits.is_empty() {
+            contributions.0.remove(contributor);
+        }
+        self.contributions.insert(launch_id, contributions);
+        self.update_launch(launch_id, |launch| {
+            launch.raised = launch.raised.saturating_sub(amount)
+        })
+    }
+
+    pub fn update_contribution<F, R>(
+        &mut self,
+        launch_id: u128,
+        contributor: &Principal,
+        f: F,
+    ) -> Result<R, String>
+    where
+        F: FnOnce(&mut Contribution) -> R,
+    {
+        let mut contributions = self.get_contributions(launch_id);
+        let contribution = contributions
+            .0
+            .get_mut(contributor)
+            .ok_or_else(|| String::from("contribution doesn't exist"))?;
+        let result = f(contribution);
+        self.contributions.insert(launch_id, contributions);
+        Ok(result)
+    }
+
+    // points refunds and distributions made by a replaced transaction at its
+    // replacement
+    pub fn replace_settlement_txid(&mut self, launch_id: u128, previous: &str, txid: &str) {
+        let mut contributions = self.get_contributions(launch_id);
+        for contribution in contributions.0.values_mut() {
+            for settled in [
+                &mut contribution.refund_txid,
+                &mut contribution.distribution_txid,
+            ] {
+                if settled.as_deref() == Some(previous) {
+                    settled.replace(txid.to_string());
+                }
+            }
+        }
+        self.contributions.insert(launch_id, contributions);
+    }
+
+    pub fn record_deposit(
+        &mut self,
+        launch_id: u128,
+        contributor: &Principal,
+        txid: String,
+    ) -> Result<(), String> {
+        self.update_contribution(launch_id, contributor, |contribution| {
+            contribution.deposits.push(txid)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        state::{read_launch_manager, write_launch_manager},
+        test_support::live_launch,
+    };
+
+    fn insert(launch_id: u128, raised: u64) {
+        let mut launch = live_launch(launch_id, TokenType::Bitcoin);
+        launch.raised = raised;
+        write_launch_manager(|manager| manager.insert_launch(launch));
+    }
+
+    fn page(descending: bool, cursor: Option<LaunchCursor>) -> (Vec<u128>, Option<LaunchCursor>) {
+        let (launches, next_cursor) = read_launch_manager(|manager| {
+            manager.page_launches(
+                &LaunchFilter::default(),
+                LaunchSort::Raised,
+                descending,
+                cursor,
+                2,
+            )
+        });
+        let ids = launches.iter().map(|launch| launch.launch_id).collect();
+        (ids, next_cursor)
+    }
+
+    #[test]
+    fn pages_follow_the_cursor() {
+        for (launch_id, raised) in [(1, 300), (2, 100), (3, 200), (4, 100), (5, 500)] {
+            insert(launch_id, raised);
+        }
+
+        let (ids, cursor) = page(false, None);
+        assert_eq!(ids, vec![2, 4]);
+        assert_eq!(
+            cursor,
+            Some(LaunchCursor {
+                key: 100,
+                launch_id: 4
+            })
+        );
+        let (ids, cursor) = page(false, cursor);
+        assert_eq!(ids, vec![3, 1]);
+        let (ids, cursor) = page(false, cursor);
+        assert_eq!(ids, vec![5]);
+        assert_eq!(cursor, None);
+
+        let (ids, cursor) = page(true, None);
+        assert_eq!(ids, vec![5, 1]);
+        let (ids, _) = page(true, cursor);
+        assert_eq!(ids, vec![3, 4]);
+    }
+
+    #[test]
+    fn launches_added_before_the_cursor_dont_shift_pages() {
+        for (launch_id, raised) in [(1, 100), (2, 200), (3, 300), (4, 400)] {
+            insert(launch_id, raised);
+        }
+        let (_, cursor) = page(false, None);
+        insert(5, 50);
+
+        let (ids, _) = page(false, cursor);
+        assert_eq!(ids, vec![3, 4]);
+    }
+
+    #[test]
+    fn contributions_buy_whole_base_units() {
+        assert_eq!(contribution_step(1_000, 0), 1_000);
+        assert_eq!(contribution_step(1_000, 2), 10);
+        assert_eq!(contribution_step(3, 2), 3);
+
+        let mut launch = live_launch(1, TokenType::Icp);
+        launch.price_per_token = 1_000;
+        launch.hard_cap = 10_000;
+        launch.raised = 9_000;
+        launch.min_contribution = Some(2_000);
+        write_launch_manager(|manager| manager.insert_launch(launch));
+        let contributor = Principal::from_slice(&[1; 10]);
+        let reserve = |amount| {
+            write_launch_manager(|manager| manager.reserve_contribution(1, contributor, amount))
+        };
+
+        assert_eq!(
+            reserve(999),
+            Err(String::from("contribution must be a multiple of 1000 e8s"))
+        );
+        // the rest of the hard cap is taken below the minimum, the remainder
+        // is left with the contributor
+        assert_eq!(reserve(1_500), Ok(1_000));
+    }
 }
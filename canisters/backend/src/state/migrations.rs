@@ -0,0 +1,206 @@
+use super::{read_config, read_utxo_manager, write_config, write_utxo_manager};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+type Migration = fn() -> Result<(), String>;
+
+// migration `i` brings the state from version `i` to `i + 1`. fields added as
+// `Option` decode from older layouts on their own and need no entry here
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] = [track_recorded_addresses];
+
+// utxos were recorded before the syncer kept a list of the addresses it follows,
+// those addresses are followed from now on
+fn track_recorded_addresses() -> Result<(), String> {
+    let recorded: Vec<String> = read_utxo_manager(|manager| {
+        manager
+            .bitcoin
+            .iter()
+            .map(|(addr, _)| addr)
+            .chain(manager.runic.iter().map(|(addr, _)| addr))
+            .collect()
+    });
+    write_utxo_manager(|manager| {
+        for addr in recorded {
+            manager.track_address(&addr);
+        }
+    });
+    Ok(())
+}
+
+fn set_schema_version(version: u32) {
+    write_config(|config| {
+        let mut temp = config.get().clone();
+        temp.schema_version.replace(version);
+        config.set(temp).expect("failed to set config");
+    });
+}
+
+// fresh state is laid out as the current version already
+pub fn init_schema_version() {
+    set_schema_version(SCHEMA_VERSION);
+}
+
+// runs the migrations the stored state hasn't been through. an error fails the
+// upgrade, which leaves the previous module and its state in place
+pub fn migrate() -> Result<(), String> {
+    let current = read_config(|config| config.schema_version());
+    if current > SCHEMA_VERSION {
+        return Err(format!(
+            "state is at version {}, newer than {}",
+            current, SCHEMA_VERSION
+        ));
+    }
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        migration()
+            .map_err(|err| format!("migration to version {} failed: {}", version + 1, err))?;
+        set_schema_version(version as u32 + 1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        borrow::Cow,
+        collections::{HashMap, HashSet},
+    };
+
+    use candid::{CandidType, Encode, Principal};
+    use ic_cdk::api::management_canister::bitcoin::{BitcoinNetwork, Outpoint, Utxo};
+    use ic_stable_structures::{storable::Bound, StableBTreeMap, StableCell, Storable};
+
+    use super::*;
+    use crate::{
+        memory::{encode, MemoryIds},
+        state::read_memory_manager,
+        updater::RuneId,
+        EcdsaPublicKey, Memory, SchnorrPublicKey,
+    };
+
+    // the config as the baseline stored it
+    #[derive(CandidType)]
+    struct BaselineConfig {
+        auth: Option<Principal>,
+        bitcoin_network: Option<BitcoinNetwork>,
+        ecdsa_public_key: Option<EcdsaPublicKey>,
+        schnorr_public_key: Option<SchnorrPublicKey>,
+        keyname: Option<String>,
+    }
+
+    #[derive(CandidType)]
+    struct BaselineRunicUtxo {
+        balance: u128,
+        utxo: Utxo,
+    }
+
+    // already encoded values, written as they are
+    struct Snapshot(Vec<u8>);
+
+    impl Storable for Snapshot {
+        fn to_bytes(&self) -> Cow<[u8]> {
+            Cow::Borrowed(&self.0)
+        }
+
+        fn from_bytes(bytes: Cow<[u8]>) -> Self {
+            Self(bytes.into_owned())
+        }
+
+        const BOUND: Bound = Bound::Unbounded;
+    }
+
+    fn utxo(id: u8) -> Utxo {
+        Utxo {
+            outpoint: Outpoint {
+                txid: vec![id; 32],
+                vout: 0,
+            },
+            value: 10_000,
+            height: 1,
+        }
+    }
+
+    // lays out the stable stores the way the baseline left them, before any
+    // of them is opened by the current code
+    fn write_baseline(config: BaselineConfig, bitcoin: &[(&str, Utxo)], runic: &[(&str, Utxo)]) {
+        let memory = |id: MemoryIds| read_memory_manager(|manager| manager.get(id.into()));
+        StableCell::<Snapshot, Memory>::init(
+            memory(MemoryIds::Config),
+            Snapshot(Encode!(&config).unwrap()),
+        )
+        .unwrap();
+
+        let mut bitcoin_mapping: StableBTreeMap<String, Snapshot, Memory> =
+            StableBTreeMap::init(memory(MemoryIds::Bitcoin));
+        for (addr, utxo) in bitcoin {
+            let utxos = HashSet::from([utxo.clone()]);
+            bitcoin_mapping.insert(addr.to_string(), Snapshot(encode(&utxos)));
+        }
+        let mut runic_mapping: StableBTreeMap<String, Snapshot, Memory> =
+            StableBTreeMap::init(memory(MemoryIds::Runic));
+        for (addr, utxo) in runic {
+            let runic_utxos = HashMap::from([(
+                RuneId {
+                    block: 840_000,
+                    tx: 1,
+                },
+                vec![BaselineRunicUtxo {
+                    balance: 1_000,
+                    utxo: utxo.clone(),
+                }],
+            )]);
+            runic_mapping.insert(addr.to_string(), Snapshot(encode(&runic_utxos)));
+        }
+    }
+
+    #[test]
+    fn migrates_baseline_state() {
+        write_baseline(
+            BaselineConfig {
+                auth: Some(Principal::anonymous()),
+                bitcoin_network: Some(BitcoinNetwork::Testnet),
+                ecdsa_public_key: None,
+                schnorr_public_key: None,
+                keyname: Some(String::from("test_key_1")),
+            },
+            &[("tb1qbitcoin", utxo(1)), ("tb1qboth", utxo(2))],
+            &[("tb1qrunic", utxo(3)), ("tb1qboth", utxo(4))],
+        );
+
+        migrate().unwrap();
+
+        assert_eq!(
+            read_config(|config| config.schema_version()),
+            SCHEMA_VERSION
+        );
+        assert_eq!(read_config(|config| config.keyname()), "test_key_1");
+        let mut tracked = read_utxo_manager(|manager| manager.tracked_addresses());
+        tracked.sort();
+        assert_eq!(tracked, vec!["tb1qbitcoin", "tb1qboth", "tb1qrunic"]);
+        // the recorded utxos still decode
+        assert_eq!(
+            read_utxo_manager(|manager| manager.get_bitcoin_utxos("tb1qboth")),
+            vec![utxo(2)]
+        );
+        assert_eq!(
+            read_utxo_manager(|manager| manager.get_runic_balance(
+                "tb1qrunic",
+                &RuneId {
+                    block: 840_000,
+                    tx: 1
+                }
+            )),
+            1_000
+        );
+    }
+
+    #[test]
+    fn refuses_state_of_a_newer_version() {
+        set_schema_version(SCHEMA_VERSION + 1);
+
+        assert!(migrate().is_err());
+        assert_eq!(
+            read_config(|config| config.schema_version()),
+            SCHEMA_VERSION + 1
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use candid::CandidType;
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::Deserialize;
+
+use crate::{
+    memory::{decode, encode, MemoryIds},
+    Memory,
+};
+
+use super::read_memory_manager;
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct PendingReveal {
+    pub launch_id: u128,
+    pub commit_txid: String,
+    pub commit_vout: u32,
+    pub commit_value: u64,
+    pub commit_address: String,
+    pub reveal_script: Vec<u8>,
+    pub control_block: Vec<u8>,
+    // unsigned reveal transaction spending the commit output
+    pub reveal_txn: Vec<u8>,
+    // set once broadcast, the reveal is kept around until it's confirmed
+    pub reveal_txid: Option<String>,
+}
+
+impl Storable for PendingReveal {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(encode(self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        decode(bytes.as_ref())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// commit txid => reveal waiting for the commit to mature
+pub type RevealMapping = StableBTreeMap<String, PendingReveal, Memory>;
+
+pub fn init_reveal_mapping() -> RevealMapping {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::Reveal.into());
+        RevealMapping::init(memory)
+    })
+}
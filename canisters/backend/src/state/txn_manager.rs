@@ -0,0 +1,77 @@
+use bitcoin::{consensus::deserialize, Amount, Transaction};
+use candid::CandidType;
+use ic_cdk::api::management_canister::bitcoin::Utxo;
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use icrc_ledger_types::icrc1::account::Account;
+use serde::Deserialize;
+
+use crate::{
+    memory::{decode, encode, MemoryIds},
+    txn_handler::TransactionType,
+    Memory,
+};
+
+use super::read_memory_manager;
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct SpentInput {
+    pub utxo: Utxo,
+    pub address: String,
+    // `None` for inputs not signed with an ecdsa key, like the commit output
+    // spent by a reveal
+    pub account: Option<Account>,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct SubmittedTransaction {
+    pub txn_type: TransactionType,
+    // signed transaction as broadcast
+    pub txn: Vec<u8>,
+    pub inputs: Vec<SpentInput>,
+    pub fee_per_vbytes: u64,
+    // output a fee bump is taken from, transactions without one can't be bumped
+    pub fee_output: Option<u32>,
+    pub submitted_at: u64,
+    // child spending the fee output to pay for this one, see `accelerate_transaction`
+    pub child_txid: Option<String>,
+}
+
+impl SubmittedTransaction {
+    pub fn transaction(&self) -> Transaction {
+        deserialize(&self.txn).expect("should decode transaction")
+    }
+
+    // the fee actually paid, spent inputs minus outputs
+    pub fn fee(&self) -> u64 {
+        let spent: u64 = self.inputs.iter().map(|input| input.utxo.value).sum();
+        let output: Amount = self
+            .transaction()
+            .output
+            .iter()
+            .map(|txout| txout.value)
+            .sum();
+        spent.saturating_sub(output.to_sat())
+    }
+}
+
+impl Storable for SubmittedTransaction {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(encode(self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        decode(bytes.as_ref())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// txid => transaction waiting to be confirmed
+pub type SubmittedMapping = StableBTreeMap<String, SubmittedTransaction, Memory>;
+
+pub fn init_submitted_mapping() -> SubmittedMapping {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::Submitted.into());
+        SubmittedMapping::init(memory)
+    })
+}
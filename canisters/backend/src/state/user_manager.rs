@@ -1,6 +1,80 @@
 use std::collections::HashSet;
 
+use candid::{CandidType, Principal};
+use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    memory::{decode, encode, MemoryIds},
+    Memory,
+};
+
+use super::read_memory_manager;
+
+#[derive(CandidType, Deserialize, Clone, Default)]
 pub struct User {
     pub launch_created: HashSet<u128>,
     pub launch_participated: HashSet<u128>,
+    // wallet the user signed in with, payouts go there instead of the
+    // deposit address
+    pub btc_address: Option<String>,
+}
+
+impl Storable for User {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(encode(self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        decode(bytes.as_ref())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+pub type UserMapping = StableBTreeMap<Principal, User, Memory>;
+
+pub fn init_user_mapping() -> UserMapping {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::Users.into());
+        UserMapping::init(memory)
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UserManager {
+    #[serde(skip, default = "init_user_mapping")]
+    pub users: UserMapping,
+}
+
+impl Default for UserManager {
+    fn default() -> Self {
+        Self {
+            users: init_user_mapping(),
+        }
+    }
+}
+
+impl UserManager {
+    pub fn get_user(&self, user: &Principal) -> User {
+        self.users.get(user).unwrap_or_default()
+    }
+
+    pub fn record_launch_created(&mut self, user: Principal, launch_id: u128) {
+        let mut detail = self.get_user(&user);
+        detail.launch_created.insert(launch_id);
+        self.users.insert(user, detail);
+    }
+
+    pub fn record_launch_participated(&mut self, user: Principal, launch_id: u128) {
+        let mut detail = self.get_user(&user);
+        detail.launch_participated.insert(launch_id);
+        self.users.insert(user, detail);
+    }
+
+    pub fn bind_address(&mut self, user: Principal, address: String) {
+        let mut detail = self.get_user(&user);
+        detail.btc_address.replace(address);
+        self.users.insert(user, detail);
+    }
 }
@@ -1,18 +1,26 @@
 use std::collections::{HashMap, HashSet};
 
-use candid::{CandidType, Decode, Encode};
-use ic_cdk::api::management_canister::bitcoin::Utxo;
+use candid::CandidType;
+use ic_cdk::api::management_canister::bitcoin::{Outpoint, Utxo};
 use ic_stable_structures::{storable::Bound, StableBTreeMap, Storable};
 use serde::{Deserialize, Serialize};
 
-use crate::{memory::MemoryIds, updater::RuneId, Memory};
+use crate::{
+    guard::OnDrop,
+    memory::{decode, encode, MemoryIds},
+    updater::RuneId,
+    Memory,
+};
 
-use super::read_memory_manager;
+use super::{read_memory_manager, write_utxo_manager};
 
-#[derive(CandidType, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct RunicUtxo {
     pub balance: u128,
     pub utxo: Utxo,
+    // stamped by the first sync seeing the output unconfirmed, recorded
+    // entries used to have no stamp and decode as `None`
+    pub recorded_at: Option<u64>,
 }
 
 impl std::hash::Hash for RunicUtxo {
@@ -27,16 +35,16 @@ impl std::borrow::Borrow<Utxo> for RunicUtxo {
     }
 }
 
-#[derive(CandidType, Deserialize)]
+#[derive(CandidType, Deserialize, Default)]
 pub struct RunicToUtxoMapping(HashMap<RuneId, HashSet<RunicUtxo>>);
 
 impl Storable for RunicToUtxoMapping {
     fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+        std::borrow::Cow::Owned(encode(self))
     }
 
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).expect("should decode")
+        decode(bytes.as_ref())
     }
 
     const BOUND: Bound = Bound::Unbounded;
@@ -51,16 +59,16 @@ pub fn init_runic_mapping() -> RunicMapping {
     })
 }
 
-#[derive(CandidType, Deserialize)]
+#[derive(CandidType, Deserialize, Default)]
 pub struct UtxoMapping(HashSet<Utxo>);
 
 impl Storable for UtxoMapping {
     fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+        std::borrow::Cow::Owned(encode(self))
     }
 
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).expect("should decode")
+        decode(bytes.as_ref())
     }
 
     const BOUND: Bound = Bound::Unbounded;
@@ -75,12 +83,85 @@ pub fn init_bitcoin_mapping() -> BitcoinMapping {
     })
 }
 
+// a transaction that neither confirmed nor got dropped within this long is
+// given up on, nodes evict unconfirmed transactions after two weeks by default.
+// applies to spent marks and to unconfirmed runic outputs alike
+pub const SPENT_MARK_TTL: u64 = 14 * 24 * 60 * 60 * 1_000_000_000;
+
+// stored marks used to be plain utxos, their other fields are skipped and
+// `spent_at` decodes as `None`
+#[derive(CandidType, Deserialize, Clone)]
+pub struct SpentUtxo {
+    pub outpoint: Outpoint,
+    // stamped by the first sync after the spend
+    pub spent_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Default)]
+pub struct SpentUtxos(Vec<SpentUtxo>);
+
+impl Storable for SpentUtxos {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(encode(self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        decode(bytes.as_ref())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+pub type SpentMapping = StableBTreeMap<String, SpentUtxos, Memory>;
+
+// utxos spent by transactions of this canister which aren't confirmed yet.
+// keeps the syncer from handing them out again
+pub fn init_spent_mapping() -> SpentMapping {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::Spent.into());
+        SpentMapping::init(memory)
+    })
+}
+
+#[derive(CandidType, Deserialize, Clone, Default)]
+pub struct SyncStatus {
+    pub tip_height: u32,
+    pub tip_block_hash: Vec<u8>,
+    pub synced_at: u64,
+}
+
+impl Storable for SyncStatus {
+    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+        std::borrow::Cow::Owned(encode(self))
+    }
+
+    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+        decode(bytes.as_ref())
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+// addresses synced with the bitcoin canister
+pub type TrackedMapping = StableBTreeMap<String, SyncStatus, Memory>;
+
+pub fn init_tracked_mapping() -> TrackedMapping {
+    read_memory_manager(|manager| {
+        let memory = manager.get(MemoryIds::Tracked.into());
+        TrackedMapping::init(memory)
+    })
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct UtxoManager {
     #[serde(skip, default = "init_runic_mapping")]
     pub runic: RunicMapping,
     #[serde(skip, default = "init_bitcoin_mapping")]
     pub bitcoin: BitcoinMapping,
+    #[serde(skip, default = "init_spent_mapping")]
+    pub spent: SpentMapping,
+    #[serde(skip, default = "init_tracked_mapping")]
+    pub tracked: TrackedMapping,
 }
 
 impl Default for UtxoManager {
@@ -88,28 +169,733 @@ impl Default for UtxoManager {
         Self {
             runic: init_runic_mapping(),
             bitcoin: init_bitcoin_mapping(),
+            spent: init_spent_mapping(),
+            tracked: init_tracked_mapping(),
         }
     }
 }
 
 impl UtxoManager {
-    pub fn record_bitcoin_utxos(&mut self, addr: &str, utxos: Vec<Utxo>) {}
-
-    pub fn record_runic_utxos(&mut self, addr: &str, runeid: RuneId, utxos: Vec<RunicUtxo>) {}
+    pub fn record_bitcoin_utxos(&mut self, addr: &str, utxos: Vec<Utxo>) {
+        let addr = addr.to_string();
+        let mut mapping = self.bitcoin.get(&addr).unwrap_or_default();
+        // an outpoint seen again (e.g. once confirmed) replaces the stale entry
+        let outpoints: HashSet<&Outpoint> = utxos.iter().map(|utxo| &utxo.outpoint).collect();
+        mapping.0.retain(|utxo| !outpoints.contains(&utxo.outpoint));
+        mapping.0.extend(utxos);
+        self.bitcoin.insert(addr, mapping);
+    }
 
-    pub fn get_bitcoin_utxo(&mut self, addr: &str) -> Option<Utxo> {
-        todo!()
+    pub fn record_runic_utxos(&mut self, addr: &str, runeid: RuneId, utxos: Vec<RunicUtxo>) {
+        let addr = addr.to_string();
+        let mut mapping = self.runic.get(&addr).unwrap_or_default();
+        let runic_utxos = mapping.0.entry(runeid).or_default();
+        let outpoints: HashSet<&Outpoint> =
+            utxos.iter().map(|runic| &runic.utxo.outpoint).collect();
+        runic_utxos.retain(|runic| !outpoints.contains(&runic.utxo.outpoint));
+        runic_utxos.extend(utxos);
+        self.runic.insert(addr, mapping);
     }
 
+    // pops the utxo holding the largest balance of the rune
     pub fn get_runic_utxo(&mut self, addr: &str, runeid: RuneId) -> Option<RunicUtxo> {
-        todo!()
+        let runic_utxo = self
+            .get_runic_utxos(addr, &runeid)
+            .into_iter()
+            .max_by_key(|runic| runic.balance)?;
+        self.remove_runic_utxos(addr, &[runic_utxo.utxo.clone()]);
+        Some(runic_utxo)
+    }
+
+    pub fn get_runic_utxos(&self, addr: &str, runeid: &RuneId) -> Vec<RunicUtxo> {
+        self.runic
+            .get(&addr.to_string())
+            .and_then(|mut mapping| mapping.0.remove(runeid))
+            .map(|utxos| utxos.into_iter().collect())
+            .unwrap_or_default()
+    }
+
+    // the runes carried by each runic utxo of the address
+    pub fn get_utxo_runes(&self, addr: &str) -> HashMap<Outpoint, Vec<(RuneId, u128)>> {
+        let mut utxo_runes: HashMap<Outpoint, Vec<(RuneId, u128)>> = HashMap::new();
+        if let Some(mapping) = self.runic.get(&addr.to_string()) {
+            for (runeid, runic_utxos) in mapping.0 {
+                for runic in runic_utxos {
+                    utxo_runes
+                        .entry(runic.utxo.outpoint)
+                        .or_default()
+                        .push((runeid, runic.balance));
+                }
+            }
+        }
+        utxo_runes
+    }
+
+    // every rune held by the address with its total balance
+    pub fn get_runic_balances(&self, addr: &str) -> Vec<(RuneId, u128)> {
+        match self.runic.get(&addr.to_string()) {
+            None => vec![],
+            Some(mapping) => mapping
+                .0
+                .into_iter()
+                .map(|(runeid, utxos)| (runeid, utxos.iter().map(|runic| runic.balance).sum()))
+                .collect(),
+        }
+    }
+
+    // a utxo can carry several runes, spending it removes it from all of them
+    pub fn remove_runic_utxos(&mut self, addr: &str, utxos: &[Utxo]) {
+        let addr = addr.to_string();
+        let mut mapping = match self.runic.get(&addr) {
+            None => return,
+            Some(mapping) => mapping,
+        };
+        let outpoints: HashSet<&Outpoint> = utxos.iter().map(|utxo| &utxo.outpoint).collect();
+        for runic_utxos in mapping.0.values_mut() {
+            runic_utxos.retain(|runic| !outpoints.contains(&runic.utxo.outpoint));
+        }
+        mapping.0.retain(|_, runic_utxos| !runic_utxos.is_empty());
+        if mapping.0.is_empty() {
+            self.runic.remove(&addr);
+        } else {
+            self.runic.insert(addr.clone(), mapping);
+        }
+        self.mark_spent(addr, utxos);
+    }
+
+    pub fn get_bitcoin_utxos(&self, addr: &str) -> Vec<Utxo> {
+        match self.bitcoin.get(&addr.to_string()) {
+            None => vec![],
+            Some(mapping) => mapping.0.into_iter().collect(),
+        }
+    }
+
+    // utxos that aren't recorded yet, like unconfirmed change, are marked all
+    // the same so the syncer doesn't hand them out once they show up
+    pub fn remove_bitcoin_utxos(&mut self, addr: &str, utxos: &[Utxo]) {
+        let addr = addr.to_string();
+        if let Some(mut mapping) = self.bitcoin.get(&addr) {
+            for utxo in utxos {
+                mapping.0.remove(utxo);
+            }
+            self.insert_bitcoin_mapping(addr.clone(), mapping);
+        }
+        self.mark_spent(addr, utxos);
+    }
+
+    // spends of this canister from `addr` which aren't confirmed yet
+    pub fn has_pending_spends(&self, addr: &str) -> bool {
+        self.spent
+            .get(&addr.to_string())
+            .is_some_and(|spent| !spent.0.is_empty())
+    }
+
+    // lifts the marks of a spend that never reached the network. the utxos are
+    // classified again by the next sync
+    pub fn unmark_spent(&mut self, addr: &str, outpoints: &[Outpoint]) {
+        let addr = addr.to_string();
+        let Some(mut spent) = self.spent.get(&addr) else {
+            return;
+        };
+        spent.0.retain(|mark| !outpoints.contains(&mark.outpoint));
+        self.insert_spent(addr, spent);
+    }
+
+    // stamps new marks with `now` and drops the ones older than `SPENT_MARK_TTL`,
+    // so a spend that got lost doesn't keep its utxos out forever
+    pub fn expire_spent(&mut self, addr: &str, now: u64) {
+        let addr = addr.to_string();
+        let Some(mut spent) = self.spent.get(&addr) else {
+            return;
+        };
+        for mark in spent.0.iter_mut() {
+            mark.spent_at.get_or_insert(now);
+        }
+        spent.0.retain(|mark| {
+            mark.spent_at
+                .is_some_and(|spent_at| now.saturating_sub(spent_at) < SPENT_MARK_TTL)
+        });
+        self.insert_spent(addr, spent);
+    }
+
+    pub fn track_address(&mut self, addr: &str) {
+        let addr = addr.to_string();
+        if !self.tracked.contains_key(&addr) {
+            self.tracked.insert(addr, SyncStatus::default());
+        }
+    }
+
+    pub fn is_tracked(&self, addr: &str) -> bool {
+        self.tracked.contains_key(&addr.to_string())
+    }
+
+    pub fn tracked_addresses(&self) -> Vec<String> {
+        self.tracked.iter().map(|(addr, _)| addr).collect()
+    }
+
+    pub fn sync_status(&self, addr: &str) -> Option<SyncStatus> {
+        self.tracked.get(&addr.to_string())
+    }
+
+    // utxos which are neither recorded nor spent by this canister yet, these have
+    // to be classified as plain or runic before they can be reconciled
+    pub fn unclassified_utxos(&self, addr: &str, utxos: &[Utxo]) -> Vec<Utxo> {
+        let addr = addr.to_string();
+        let mut known: HashSet<Outpoint> = HashSet::new();
+        if let Some(mapping) = self.bitcoin.get(&addr) {
+            known.extend(mapping.0.into_iter().map(|utxo| utxo.outpoint));
+        }
+        if let Some(spent) = self.spent.get(&addr) {
+            known.extend(spent.0.into_iter().map(|mark| mark.outpoint));
+        }
+        if let Some(mapping) = self.runic.get(&addr) {
+            known.extend(
+                mapping
+                    .0
+                    .into_values()
+                    .flatten()
+                    .map(|runic| runic.utxo.outpoint),
+            );
+        }
+        utxos
+            .iter()
+            .filter(|utxo| !known.contains(&utxo.outpoint))
+            .cloned()
+            .collect()
+    }
+
+    // replaces the recorded utxos of the address with what the bitcoin canister
+    // reported, minus whatever this canister already spent. `runic` holds the
+    // newly seen utxos carrying runes, `pending` the ones the indexer couldn't
+    // classify yet which are left out until it catches up
+    pub fn reconcile_utxos(
+        &mut self,
+        addr: &str,
+        utxos: Vec<Utxo>,
+        runic: Vec<(RuneId, RunicUtxo)>,
+        pending: Vec<Utxo>,
+        status: SyncStatus,
+    ) {
+        let addr = addr.to_string();
+        let fetched: HashMap<Outpoint, Utxo> = utxos
+            .iter()
+            .map(|utxo| (utxo.outpoint.clone(), utxo.clone()))
+            .collect();
+
+        let mut spent = self.spent.get(&addr).unwrap_or_default();
+        // spends which no longer show up have been confirmed
+        spent.0.retain(|mark| fetched.contains_key(&mark.outpoint));
+        let mut excluded: HashSet<Outpoint> =
+            spent.0.iter().map(|mark| mark.outpoint.clone()).collect();
+        excluded.extend(pending.into_iter().map(|utxo| utxo.outpoint));
+
+        let mut mapping = self.runic.get(&addr).unwrap_or_default();
+        for runic_utxos in mapping.0.values_mut() {
+            *runic_utxos = runic_utxos
+                .drain()
+                .filter_map(|mut runic| match fetched.get(&runic.utxo.outpoint) {
+                    Some(utxo) => {
+                        runic.utxo = utxo.clone();
+                        Some(runic)
+                    }
+                    // output of a transaction of this canister, not confirmed yet
+                    None if runic.utxo.height == 0 => {
+                        let recorded_at = *runic.recorded_at.get_or_insert(status.synced_at);
+                        (status.synced_at.saturating_sub(recorded_at) < SPENT_MARK_TTL)
+                            .then_some(runic)
+                    }
+                    None => None,
+                })
+                .collect();
+        }
+        for (runeid, runic) in runic {
+            let runic_utxos = mapping.0.entry(runeid).or_default();
+            runic_utxos.retain(|recorded| recorded.utxo.outpoint != runic.utxo.outpoint);
+            runic_utxos.insert(runic);
+        }
+        mapping.0.retain(|_, runic_utxos| !runic_utxos.is_empty());
+        excluded.extend(
+            mapping
+                .0
+                .values()
+                .flatten()
+                .map(|runic| runic.utxo.outpoint.clone()),
+        );
+        if mapping.0.is_empty() {
+            self.runic.remove(&addr);
+        } else {
+            self.runic.insert(addr.clone(), mapping);
+        }
+
+        let unspent = utxos
+            .into_iter()
+            .filter(|utxo| !excluded.contains(&utxo.outpoint))
+            .collect();
+        self.insert_bitcoin_mapping(addr.clone(), UtxoMapping(unspent));
+
+        self.insert_spent(addr.clone(), spent);
+        self.tracked.insert(addr, status);
+    }
+
+    // points the runic outputs of a replaced transaction at its replacement,
+    // `replacement` holds the outputs of the new transaction paying to `addr`
+    pub fn replace_runic_outputs(&mut self, addr: &str, previous: &[u8], replacement: &[Utxo]) {
+        let addr = addr.to_string();
+        let Some(mut mapping) = self.runic.get(&addr) else {
+            return;
+        };
+        for runic_utxos in mapping.0.values_mut() {
+            *runic_utxos = runic_utxos
+                .drain()
+                .filter_map(|mut runic| {
+                    if runic.utxo.outpoint.txid != previous {
+                        return Some(runic);
+                    }
+                    let utxo = replacement
+                        .iter()
+                        .find(|utxo| utxo.outpoint.vout == runic.utxo.outpoint.vout)?;
+                    runic.utxo = utxo.clone();
+                    Some(runic)
+                })
+                .collect();
+        }
+        mapping.0.retain(|_, runic_utxos| !runic_utxos.is_empty());
+        if mapping.0.is_empty() {
+            self.runic.remove(&addr);
+        } else {
+            self.runic.insert(addr, mapping);
+        }
     }
 
     pub fn get_bitcoin_balance(&self, addr: &str) -> u64 {
-        todo!()
+        match self.bitcoin.get(&addr.to_string()) {
+            None => 0,
+            Some(mapping) => mapping.0.iter().map(|utxo| utxo.value).sum(),
+        }
     }
 
     pub fn get_runic_balance(&self, addr: &str, runeid: &RuneId) -> u128 {
-        todo!()
+        self.runic
+            .get(&addr.to_string())
+            .and_then(|mapping| {
+                mapping
+                    .0
+                    .get(runeid)
+                    .map(|utxos| utxos.iter().map(|runic| runic.balance).sum())
+            })
+            .unwrap_or(0)
+    }
+
+    fn mark_spent(&mut self, addr: String, utxos: &[Utxo]) {
+        if utxos.is_empty() {
+            return;
+        }
+        let mut spent = self.spent.get(&addr).unwrap_or_default();
+        for utxo in utxos {
+            if !spent.0.iter().any(|mark| mark.outpoint == utxo.outpoint) {
+                spent.0.push(SpentUtxo {
+                    outpoint: utxo.outpoint.clone(),
+                    spent_at: None,
+                });
+            }
+        }
+        self.spent.insert(addr, spent);
+    }
+
+    fn insert_spent(&mut self, addr: String, spent: SpentUtxos) {
+        if spent.0.is_empty() {
+            self.spent.remove(&addr);
+        } else {
+            self.spent.insert(addr, spent);
+        }
+    }
+
+    fn insert_bitcoin_mapping(&mut self, addr: String, mapping: UtxoMapping) {
+        if mapping.0.is_empty() {
+            self.bitcoin.remove(&addr);
+        } else {
+            self.bitcoin.insert(addr, mapping);
+        }
+    }
+}
+
+type SpentMarks = Vec<(String, Vec<Outpoint>)>;
+
+// the utxos a transaction takes out of the utxo manager until it's broadcast,
+// their spent marks are lifted again unless it is
+#[must_use]
+pub struct PendingSpend(OnDrop<SpentMarks, fn(SpentMarks)>);
+
+impl PendingSpend {
+    pub fn new(addr: &str, utxos: &[Utxo]) -> Self {
+        let mut spend = Self(OnDrop::new(vec![], unmark_spent));
+        spend.add(addr, utxos);
+        spend
+    }
+
+    pub fn add(&mut self, addr: &str, utxos: &[Utxo]) {
+        let outpoints = utxos.iter().map(|utxo| utxo.outpoint.clone()).collect();
+        self.0.push((addr.to_string(), outpoints));
+    }
+
+    pub fn merge(&mut self, other: Self) {
+        self.0.append(&mut other.0.disarm());
+    }
+
+    pub fn broadcast(self) {
+        self.0.disarm();
+    }
+}
+
+fn unmark_spent(marks: SpentMarks) {
+    write_utxo_manager(|manager| {
+        for (addr, outpoints) in marks.iter() {
+            manager.unmark_spent(addr, outpoints);
+        }
+    });
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub enum SelectionStrategy {
+    LargestFirst,
+    // looks for an input set that needs no change output, i.e. overshoots
+    // `target` by at most `cost_of_change`. falls back to largest first
+    BranchAndBound { cost_of_change: u64 },
+}
+
+const BNB_MAX_TRIES: usize = 100_000;
+
+pub fn select_utxos(utxos: &[Utxo], target: u64, strategy: SelectionStrategy) -> Option<Vec<Utxo>> {
+    let mut sorted: Vec<&Utxo> = utxos.iter().collect();
+    sorted.sort_by(|a, b| b.value.cmp(&a.value));
+
+    match strategy {
+        SelectionStrategy::LargestFirst => select_largest_first(&sorted, target),
+        SelectionStrategy::BranchAndBound { cost_of_change } => {
+            select_branch_and_bound(&sorted, target, cost_of_change)
+                .or_else(|| select_largest_first(&sorted, target))
+        }
+    }
+}
+
+fn select_largest_first(sorted: &[&Utxo], target: u64) -> Option<Vec<Utxo>> {
+    let (mut selected, mut total) = (vec![], 0);
+    for utxo in sorted {
+        if total >= target {
+            break;
+        }
+        total += utxo.value;
+        selected.push((*utxo).clone());
+    }
+    (total >= target).then_some(selected)
+}
+
+struct BranchAndBound<'a> {
+    utxos: &'a [&'a Utxo],
+    target: u64,
+    upper_bound: u64,
+    tries: usize,
+    selection: Vec<usize>,
+    // (excess, selection)
+    best: Option<(u64, Vec<usize>)>,
+}
+
+impl BranchAndBound<'_> {
+    fn search(&mut self, index: usize, selected_value: u64, remaining_value: u64) {
+        if self.tries == 0 || matches!(self.best, Some((0, _))) {
+            return;
+        }
+        self.tries -= 1;
+
+        if selected_value > self.upper_bound {
+            return;
+        }
+        if selected_value >= self.target {
+            let excess = selected_value - self.target;
+            if self.best.as_ref().map_or(true, |(best, _)| excess < *best) {
+                self.best = Some((excess, self.selection.clone()));
+            }
+            return;
+        }
+        if index == self.utxos.len() || selected_value + remaining_value < self.target {
+            return;
+        }
+
+        let value = self.utxos[index].value;
+        self.selection.push(index);
+        self.search(index + 1, selected_value + value, remaining_value - value);
+        self.selection.pop();
+        self.search(index + 1, selected_value, remaining_value - value);
+    }
+}
+
+fn select_branch_and_bound(
+    sorted: &[&Utxo],
+    target: u64,
+    cost_of_change: u64,
+) -> Option<Vec<Utxo>> {
+    let total: u64 = sorted.iter().map(|utxo| utxo.value).sum();
+    if total < target {
+        return None;
+    }
+    let mut bnb = BranchAndBound {
+        utxos: sorted,
+        target,
+        upper_bound: target + cost_of_change,
+        tries: BNB_MAX_TRIES,
+        selection: vec![],
+        best: None,
+    };
+    bnb.search(0, 0, total);
+    bnb.best.map(|(_, selection)| {
+        selection
+            .into_iter()
+            .map(|index| sorted[index].clone())
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::read_utxo_manager;
+
+    fn utxo(id: u8, value: u64) -> Utxo {
+        Utxo {
+            outpoint: Outpoint {
+                txid: vec![id; 32],
+                vout: 0,
+            },
+            value,
+            height: 1,
+        }
+    }
+
+    const ADDR: &str = "bcrt1qaddress";
+
+    fn runic(id: u8, value: u64, balance: u128) -> RunicUtxo {
+        RunicUtxo {
+            balance,
+            utxo: utxo(id, value),
+            recorded_at: None,
+        }
+    }
+
+    const RUNE: RuneId = RuneId { block: 1, tx: 1 };
+
+    fn values(utxos: Option<Vec<Utxo>>) -> Option<Vec<u64>> {
+        utxos.map(|utxos| utxos.into_iter().map(|utxo| utxo.value).collect())
+    }
+
+    #[test]
+    fn records_and_removes_bitcoin_utxos() {
+        let mut manager = UtxoManager::default();
+        manager.record_bitcoin_utxos(ADDR, vec![utxo(1, 1_000), utxo(2, 2_000)]);
+        // seen again once confirmed, the stale entry is replaced
+        let mut confirmed = utxo(1, 1_000);
+        confirmed.height = 5;
+        manager.record_bitcoin_utxos(ADDR, vec![confirmed.clone()]);
+        assert_eq!(manager.get_bitcoin_utxos(ADDR).len(), 2);
+        assert_eq!(manager.get_bitcoin_balance(ADDR), 3_000);
+
+        manager.remove_bitcoin_utxos(ADDR, &[confirmed]);
+        assert_eq!(manager.get_bitcoin_utxos(ADDR), vec![utxo(2, 2_000)]);
+        assert!(manager.has_pending_spends(ADDR));
+
+        manager.remove_bitcoin_utxos(ADDR, &[utxo(2, 2_000)]);
+        assert!(manager.get_bitcoin_utxos(ADDR).is_empty());
+        assert!(!manager.bitcoin.contains_key(&ADDR.to_string()));
+    }
+
+    #[test]
+    fn unrecorded_utxos_are_marked_spent() {
+        let mut manager = UtxoManager::default();
+        let mut change = utxo(1, 1_000);
+        change.height = 0;
+        manager.remove_bitcoin_utxos(ADDR, &[change]);
+        assert!(manager.has_pending_spends(ADDR));
+        assert!(manager
+            .unclassified_utxos(ADDR, &[utxo(1, 1_000)])
+            .is_empty());
+    }
+
+    #[test]
+    fn removes_runic_utxos_from_every_rune() {
+        let other = RuneId { block: 2, tx: 7 };
+        let mut manager = UtxoManager::default();
+        manager.record_runic_utxos(ADDR, RUNE, vec![runic(1, 546, 100), runic(2, 546, 50)]);
+        manager.record_runic_utxos(ADDR, other, vec![runic(1, 546, 7)]);
+        assert_eq!(manager.get_runic_balance(ADDR, &RUNE), 150);
+
+        let popped = manager.get_runic_utxo(ADDR, RUNE).unwrap();
+        assert_eq!(popped.balance, 100);
+        assert_eq!(manager.get_runic_balance(ADDR, &RUNE), 50);
+        assert_eq!(manager.get_runic_balance(ADDR, &other), 0);
+        assert!(manager.has_pending_spends(ADDR));
+    }
+
+    #[test]
+    fn reconciles_with_fetched_utxos() {
+        let mut manager = UtxoManager::default();
+        manager.record_bitcoin_utxos(ADDR, vec![utxo(1, 1_000), utxo(2, 2_000)]);
+        manager.record_runic_utxos(ADDR, RUNE, vec![runic(3, 546, 10)]);
+        let mut unconfirmed = runic(4, 546, 5);
+        unconfirmed.utxo.height = 0;
+        manager.record_runic_utxos(ADDR, RUNE, vec![unconfirmed]);
+        manager.remove_bitcoin_utxos(ADDR, &[utxo(1, 1_000)]);
+
+        // 2 and the runic 3 got spent elsewhere, 5 is new and plain, 6 is
+        // new and runic, 7 isn't indexed yet
+        let fetched = vec![utxo(1, 1_000), utxo(5, 5_000), utxo(6, 546), utxo(7, 700)];
+        let unclassified = manager.unclassified_utxos(ADDR, &fetched);
+        assert_eq!(
+            unclassified,
+            vec![utxo(5, 5_000), utxo(6, 546), utxo(7, 700)]
+        );
+
+        manager.reconcile_utxos(
+            ADDR,
+            fetched,
+            vec![(RUNE, runic(6, 546, 20))],
+            vec![utxo(7, 700)],
+            SyncStatus::default(),
+        );
+        assert_eq!(manager.get_bitcoin_utxos(ADDR), vec![utxo(5, 5_000)]);
+        // the unconfirmed output of this canister is kept until it shows up
+        assert_eq!(manager.get_runic_balance(ADDR, &RUNE), 25);
+        // the spend of 1 isn't confirmed yet
+        assert!(manager.has_pending_spends(ADDR));
+        assert!(manager.is_tracked(ADDR));
+
+        manager.reconcile_utxos(ADDR, vec![], vec![], vec![], SyncStatus::default());
+        assert!(!manager.has_pending_spends(ADDR));
+        assert_eq!(manager.get_runic_balance(ADDR, &RUNE), 5);
+
+        // the unconfirmed output is given up on once the ttl runs out
+        let expired = SyncStatus {
+            synced_at: SPENT_MARK_TTL,
+            ..Default::default()
+        };
+        manager.reconcile_utxos(ADDR, vec![], vec![], vec![], expired);
+        assert_eq!(manager.get_runic_balance(ADDR, &RUNE), 0);
+    }
+
+    #[test]
+    fn replaced_runic_outputs_move_to_the_replacement() {
+        let mut manager = UtxoManager::default();
+        let mut unconfirmed = runic(1, 546, 10);
+        unconfirmed.utxo.height = 0;
+        unconfirmed.utxo.outpoint.vout = 1;
+        manager.record_runic_utxos(ADDR, RUNE, vec![unconfirmed, runic(2, 546, 5)]);
+
+        let mut replacement = utxo(9, 546);
+        replacement.height = 0;
+        replacement.outpoint.vout = 1;
+        manager.replace_runic_outputs(ADDR, &[1; 32], &[replacement.clone()]);
+
+        let mut outpoints: Vec<Outpoint> = manager
+            .get_runic_utxos(ADDR, &RUNE)
+            .into_iter()
+            .map(|runic| runic.utxo.outpoint)
+            .collect();
+        outpoints.sort();
+        assert_eq!(outpoints, vec![utxo(2, 546).outpoint, replacement.outpoint]);
+        assert_eq!(manager.get_runic_balance(ADDR, &RUNE), 15);
+    }
+
+    #[test]
+    fn largest_first_selection() {
+        let utxos = [utxo(1, 1_000), utxo(2, 5_000), utxo(3, 3_000)];
+        let select = |target| {
+            values(select_utxos(
+                &utxos,
+                target,
+                SelectionStrategy::LargestFirst,
+            ))
+        };
+
+        assert_eq!(select(0), Some(vec![]));
+        assert_eq!(select(4_000), Some(vec![5_000]));
+        assert_eq!(select(6_000), Some(vec![5_000, 3_000]));
+        assert_eq!(select(9_000), Some(vec![5_000, 3_000, 1_000]));
+        assert_eq!(select(9_001), None);
+    }
+
+    #[test]
+    fn branch_and_bound_avoids_change() {
+        let utxos = [
+            utxo(1, 1_000),
+            utxo(2, 5_000),
+            utxo(3, 3_000),
+            utxo(4, 4_100),
+        ];
+        let select = |target, cost_of_change| {
+            values(select_utxos(
+                &utxos,
+                target,
+                SelectionStrategy::BranchAndBound { cost_of_change },
+            ))
+        };
+
+        // 3_000 + 1_000 hits the target exactly where largest first takes 5_000
+        assert_eq!(select(4_000, 0), Some(vec![3_000, 1_000]));
+        // within the cost of change the smallest excess wins
+        assert_eq!(select(4_050, 100), Some(vec![4_100]));
+        assert_eq!(select(8_000, 200), Some(vec![5_000, 3_000]));
+        // nothing close enough, falls back to largest first
+        assert_eq!(select(4_500, 10), Some(vec![5_000]));
+        assert_eq!(select(13_101, 1_000), None);
+    }
+
+    #[test]
+    fn spent_marks_expire_after_ttl() {
+        let mut manager = UtxoManager::default();
+        manager.record_bitcoin_utxos(ADDR, vec![utxo(1, 1_000), utxo(2, 2_000)]);
+        manager.remove_bitcoin_utxos(ADDR, &[utxo(1, 1_000)]);
+        assert!(manager.has_pending_spends(ADDR));
+
+        // the first sync stamps the mark, it's kept until the ttl runs out
+        manager.expire_spent(ADDR, 100);
+        manager.expire_spent(ADDR, 100 + SPENT_MARK_TTL - 1);
+        assert!(manager.has_pending_spends(ADDR));
+        assert!(manager
+            .unclassified_utxos(ADDR, &[utxo(1, 1_000), utxo(2, 2_000)])
+            .is_empty());
+
+        manager.expire_spent(ADDR, 100 + SPENT_MARK_TTL);
+        assert!(!manager.has_pending_spends(ADDR));
+        let unclassified = manager.unclassified_utxos(ADDR, &[utxo(1, 1_000), utxo(2, 2_000)]);
+        assert_eq!(unclassified, vec![utxo(1, 1_000)]);
+    }
+
+    #[test]
+    fn unmarked_utxos_are_classified_again() {
+        let mut manager = UtxoManager::default();
+        manager.record_bitcoin_utxos(ADDR, vec![utxo(1, 1_000), utxo(2, 2_000)]);
+        manager.remove_bitcoin_utxos(ADDR, &[utxo(1, 1_000), utxo(2, 2_000)]);
+
+        manager.unmark_spent(ADDR, &[utxo(2, 2_000).outpoint]);
+        let unclassified = manager.unclassified_utxos(ADDR, &[utxo(1, 1_000), utxo(2, 2_000)]);
+        assert_eq!(unclassified, vec![utxo(2, 2_000)]);
+
+        manager.unmark_spent(ADDR, &[utxo(1, 1_000).outpoint]);
+        assert!(!manager.has_pending_spends(ADDR));
+    }
+
+    #[test]
+    fn pending_spend_unmarks_unless_broadcast() {
+        write_utxo_manager(|manager| {
+            manager.record_bitcoin_utxos(ADDR, vec![utxo(1, 1_000), utxo(2, 2_000)]);
+            manager.remove_bitcoin_utxos(ADDR, &[utxo(1, 1_000), utxo(2, 2_000)]);
+        });
+        let dropped = PendingSpend::new(ADDR, &[utxo(1, 1_000)]);
+        let broadcast = PendingSpend::new(ADDR, &[utxo(2, 2_000)]);
+        drop(dropped);
+        broadcast.broadcast();
+
+        let unclassified = read_utxo_manager(|manager| {
+            manager.unclassified_utxos(ADDR, &[utxo(1, 1_000), utxo(2, 2_000)])
+        });
+        assert_eq!(unclassified, vec![utxo(1, 1_000)]);
     }
 }
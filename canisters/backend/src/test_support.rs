@@ -0,0 +1,169 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    future::Future,
+    pin::pin,
+    task::{Context, Poll, Waker},
+};
+
+use candid::Principal;
+use icrc_ledger_types::icrc1::account::{Account, Subaccount};
+
+use crate::{
+    chain::icrc::Ledger,
+    state::{
+        launch_manager::{Launch, LaunchStatus},
+        read_launch_manager, write_launch_manager,
+    },
+    token_type::TokenType,
+};
+
+// a live launch selling 1 token per sat, without terms or premine
+pub fn live_launch(launch_id: u128, raise_in: TokenType) -> Launch {
+    Launch {
+        created_by: Principal::from_slice(&[9; 10]),
+        launch_id,
+        runename: String::from("TESTRUNE"),
+        divisibility: 0,
+        symbol: None,
+        total_supply: 1_000_000,
+        terms: None,
+        mints: None,
+        rune_id: None,
+        website: None,
+        x: None,
+        telegram: None,
+        openchat: None,
+        hard_cap: 100_000,
+        soft_cap: 10_000,
+        price_per_token: 1,
+        min_contribution: None,
+        max_contribution: None,
+        raise_in,
+        raised: 0,
+        commit_txid: String::new(),
+        reveal_txid: None,
+        premine_utxo: None,
+        payout_txid: None,
+        last_error: None,
+        created_at: 0,
+        starts_at: 0,
+        ends_at: 0,
+        status: LaunchStatus::Live,
+    }
+}
+
+pub fn contributor(id: u8) -> Principal {
+    Principal::from_slice(&[id; 10])
+}
+
+// stores `launch` with the contributions of each contributor id, then moves it
+// to `status`
+pub fn insert_launch(launch: Launch, contributions: &[(u8, u64)], status: LaunchStatus) -> Launch {
+    let launch_id = launch.launch_id;
+    write_launch_manager(|manager| {
+        manager.insert_launch(launch);
+        for (id, amount) in contributions {
+            manager
+                .reserve_contribution(launch_id, contributor(*id), *amount)
+                .unwrap();
+        }
+        manager
+            .update_launch(launch_id, |launch| launch.status = status)
+            .unwrap();
+    });
+    read_launch_manager(|manager| manager.get_launch(launch_id)).unwrap()
+}
+
+// the stand-in never waits, so its futures complete on the first poll
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    match future
+        .as_mut()
+        .poll(&mut Context::from_waker(Waker::noop()))
+    {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("future awaits something besides the ledger"),
+    }
+}
+
+// balances kept in memory, `canister` owns the subaccounts transfers are
+// made from. allowances aren't checked
+pub struct StandInLedger {
+    pub canister: Principal,
+    pub fee: u64,
+    pub balances: RefCell<HashMap<Account, u64>>,
+    pub blocks: RefCell<Vec<(Account, Account, u64)>>,
+}
+
+impl StandInLedger {
+    pub fn new(fee: u64) -> Self {
+        Self {
+            canister: Principal::from_slice(&[1; 10]),
+            fee,
+            balances: RefCell::default(),
+            blocks: RefCell::default(),
+        }
+    }
+
+    // the subaccount of the canister holding a launch's funds
+    pub fn escrow(&self) -> Account {
+        Account {
+            owner: self.canister,
+            subaccount: Some([7; 32]),
+        }
+    }
+
+    pub fn mint(&self, account: Account, amount: u64) {
+        *self.balances.borrow_mut().entry(account).or_default() += amount;
+    }
+
+    pub fn balance(&self, account: Account) -> u64 {
+        self.balances
+            .borrow()
+            .get(&account)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn move_funds(&self, from: Account, to: Account, amount: u64) -> Result<u64, String> {
+        let debit = amount + self.fee;
+        let mut balances = self.balances.borrow_mut();
+        let balance = balances.entry(from).or_default();
+        if *balance < debit {
+            return Err(String::from("transfer failed: InsufficientFunds"));
+        }
+        *balance -= debit;
+        *balances.entry(to).or_default() += amount;
+        let mut blocks = self.blocks.borrow_mut();
+        blocks.push((from, to, amount));
+        Ok(blocks.len() as u64 - 1)
+    }
+}
+
+impl Ledger for StandInLedger {
+    async fn fee(&self) -> Result<u64, String> {
+        Ok(self.fee)
+    }
+
+    async fn balance_of(&self, account: Account) -> Result<u64, String> {
+        Ok(self.balance(account))
+    }
+
+    async fn transfer_from(&self, from: Account, to: Account, amount: u64) -> Result<u64, String> {
+        self.move_funds(from, to, amount)
+    }
+
+    async fn transfer(
+        &self,
+        from_subaccount: Option<Subaccount>,
+        to: Account,
+        amount: u64,
+    ) -> Result<u64, String> {
+        let from = Account {
+            owner: self.canister,
+            subaccount: from_subaccount,
+        };
+        self.move_funds(from, to, amount)
+    }
+}
@@ -1,7 +1,33 @@
-use candid::CandidType;
+use candid::{CandidType, Principal};
 use serde::Deserialize;
 
-#[derive(CandidType, Deserialize)]
+use crate::state::read_config;
+
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum TokenType {
     Bitcoin,
+    Icp,
+    CkBtc,
+    Icrc { ledger: Principal },
+}
+
+impl TokenType {
+    // ledger the raised funds are held on, `None` for native bitcoin
+    pub fn ledger(&self) -> Result<Option<Principal>, String> {
+        match self {
+            Self::Bitcoin => Ok(None),
+            Self::Icp => Ok(Some(read_config(|config| config.icp_ledger()))),
+            Self::CkBtc => read_config(|config| config.ckbtc_ledger()).map(Some),
+            Self::Icrc { ledger } => Ok(Some(*ledger)),
+        }
+    }
+
+    // the smallest unit amounts of the token are given in
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Self::Bitcoin | Self::CkBtc => "sats",
+            Self::Icp => "e8s",
+            Self::Icrc { .. } => "base units",
+        }
+    }
 }
@@ -1,10 +1,34 @@
-use candid::CandidType;
+use candid::{CandidType, Principal};
+use serde::Deserialize;
 
 #[derive(CandidType)]
 pub enum SubmittedTxidType {
     Bitcoin { txid: String },
+    Icrc { ledger: Principal, block_index: u64 },
 }
 
+#[derive(CandidType, Deserialize, Clone)]
 pub enum TransactionType {
-    Etching {},
+    // commit of a launch's etching
+    Etching {
+        launch_id: u128,
+    },
+    Reveal {
+        launch_id: u128,
+        commit_txid: String,
+    },
+    Payout {
+        launch_id: u128,
+    },
+    Refund {
+        launch_id: u128,
+    },
+    // one batch of a launch's distribution
+    Distribution {
+        launch_id: u128,
+    },
+    // child paying for a commit, see `accelerate_transaction`
+    Acceleration {
+        parent_txid: String,
+    },
 }
@@ -1,7 +1,17 @@
-use candid::{CandidType, Decode, Encode};
+use candid::CandidType;
 use ic_stable_structures::{storable::Bound, Storable};
 use serde::Deserialize;
 
+use crate::memory::{decode, encode};
+
+pub mod launch_ticker;
+pub mod ord_canister;
+pub mod rune_indexer;
+pub mod settlement;
+pub mod siwb_provider;
+pub mod txn_watcher;
+pub mod utxo_syncer;
+
 #[derive(CandidType, Deserialize, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct RuneId {
     pub block: u32,
@@ -10,14 +20,30 @@ pub struct RuneId {
 
 impl Storable for RuneId {
     fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        std::borrow::Cow::Owned(Encode!(self).expect("should encode"))
+        std::borrow::Cow::Owned(encode(self))
     }
 
     fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).expect("should decode")
+        decode(bytes.as_ref())
     }
 
     const BOUND: Bound = Bound::Unbounded;
 }
 
-mod ord_canister {}
+impl From<ordinals::RuneId> for RuneId {
+    fn from(id: ordinals::RuneId) -> Self {
+        Self {
+            block: id.block as u32,
+            tx: id.tx as u64,
+        }
+    }
+}
+
+impl From<RuneId> for ordinals::RuneId {
+    fn from(id: RuneId) -> Self {
+        Self {
+            block: id.block as u64,
+            tx: id.tx as u32,
+        }
+    }
+}
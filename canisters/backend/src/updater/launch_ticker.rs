@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use crate::{
+    chain::btc::refresh_fee_percentiles,
+    state::{
+        launch_manager::{Launch, LaunchStatus},
+        read_launch_manager, write_launch_manager,
+    },
+};
+
+use super::{
+    ord_canister::{get_etching, parse_rune_id},
+    settlement::settle_launches,
+};
+
+const TICK_INTERVAL: Duration = Duration::from_secs(300);
+
+pub fn start_ticker() {
+    ic_cdk_timers::set_timer_interval(TICK_INTERVAL, || ic_cdk::spawn(tick()));
+}
+
+async fn tick() {
+    // keeps the fee rates quoted by queries current
+    let _ = refresh_fee_percentiles().await;
+    check_etchings().await;
+    advance_launches(ic_cdk::api::time());
+    settle_launches().await;
+}
+
+// a launch is etched once the indexer knows the rune id of its reveal, the
+// distribution can't be built without it
+async fn check_etchings() {
+    let pending =
+        read_launch_manager(|manager| manager.launches_with_status(LaunchStatus::PendingEtching));
+
+    for launch in pending {
+        let reveal_txid = match launch.reveal_txid {
+            None => continue,
+            Some(txid) => txid,
+        };
+        let rune_id = match get_etching(reveal_txid).await {
+            Ok(None) => continue,
+            Ok(Some(etching)) => parse_rune_id(&etching.rune_id),
+            Err(err) => Err(err),
+        };
+
+        write_launch_manager(|manager| match rune_id {
+            Err(err) => manager.record_result(launch.launch_id, &Err(err)),
+            Ok(rune_id) => {
+                let _ = manager.update_launch(launch.launch_id, |launch| {
+                    launch.rune_id.replace(rune_id);
+                    launch.last_error = None;
+                });
+                let _ = manager.transition(launch.launch_id, LaunchStatus::Etched);
+            }
+        });
+    }
+}
+
+pub fn advance_launches(now: u64) {
+    write_launch_manager(|manager| {
+        let due: Vec<Launch> = manager
+            .launches
+            .iter()
+            .map(|(_, launch)| launch)
+            .filter(|launch| launch.next_status(now).is_some())
+            .collect();
+
+        for mut launch in due {
+            // a launch can pass through several states in one tick, e.g. an
+            // etching confirming after its start time
+            while let Some(next) = launch.next_status(now) {
+                if manager.transition(launch.launch_id, next).is_err() {
+                    break;
+                }
+                launch.status = next;
+            }
+        }
+    });
+}
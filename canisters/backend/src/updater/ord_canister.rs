@@ -0,0 +1,144 @@
+use std::str::FromStr;
+
+use candid::{CandidType, Principal, Reserved};
+use ic_cdk::api::management_canister::bitcoin::Utxo;
+use serde::Deserialize;
+
+use crate::{
+    chain::btc::utils::slice_to_txid,
+    state::{read_config, utxo_manager::RunicUtxo},
+};
+
+use super::RuneId;
+
+// the indexer limits how many outputs can be queried at once
+const MAX_OUTPOINTS_PER_QUERY: usize = 64;
+
+// only the fields used here, candid skips the rest of the indexer's records
+#[derive(CandidType, Deserialize, Clone)]
+pub struct RuneBalance {
+    pub rune_id: String,
+    pub amount: u128,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct GetEtchingResult {
+    pub confirmations: u32,
+    pub rune_id: String,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct RuneEntry {
+    pub mints: u128,
+}
+
+pub fn parse_rune_id(rune_id: &str) -> Result<RuneId, String> {
+    ordinals::RuneId::from_str(rune_id)
+        .map(RuneId::from)
+        .map_err(|_| format!("invalid rune id: {}", rune_id))
+}
+
+fn indexer() -> Option<Principal> {
+    read_config(|config| config.ord_indexer)
+}
+
+pub async fn get_latest_block(indexer: Principal) -> Result<u32, String> {
+    ic_cdk::call::<(), (u32, String)>(indexer, "get_latest_block", ())
+        .await
+        .map(|(height, _)| height)
+        .map_err(|(code, msg)| format!("indexer rejected: {:?} {}", code, msg))
+}
+
+pub async fn get_etching(txid: String) -> Result<Option<GetEtchingResult>, String> {
+    let indexer = indexer().ok_or_else(|| String::from("ord indexer not configured"))?;
+    ic_cdk::call::<(String,), (Option<GetEtchingResult>,)>(indexer, "get_etching", (txid,))
+        .await
+        .map(|(etching,)| etching)
+        .map_err(|(code, msg)| format!("indexer rejected: {:?} {}", code, msg))
+}
+
+// mints of the rune the indexer has seen confirmed
+pub async fn get_rune_mints(rune_id: RuneId) -> Result<Option<u128>, String> {
+    let indexer = indexer().ok_or_else(|| String::from("ord indexer not configured"))?;
+    let rune_id = ordinals::RuneId::from(rune_id).to_string();
+    ic_cdk::call::<(String,), (Option<RuneEntry>,)>(indexer, "get_rune_by_id", (rune_id,))
+        .await
+        .map(|(entry,)| entry.map(|entry| entry.mints))
+        .map_err(|(code, msg)| format!("indexer rejected: {:?} {}", code, msg))
+}
+
+async fn get_rune_balances_for_outputs(
+    indexer: Principal,
+    outpoints: Vec<String>,
+) -> Result<Vec<Option<Vec<RuneBalance>>>, String> {
+    ic_cdk::call::<(Vec<String>,), (Result<Vec<Option<Vec<RuneBalance>>>, Reserved>,)>(
+        indexer,
+        "get_rune_balances_for_outputs",
+        (outpoints,),
+    )
+    .await
+    .map_err(|(code, msg)| format!("indexer rejected: {:?} {}", code, msg))?
+    .0
+    .map_err(|_| String::from("indexer failed to return balances"))
+}
+
+#[derive(Default)]
+pub struct Classification {
+    pub runic: Vec<(RuneId, RunicUtxo)>,
+    // not indexed yet
+    pub pending: Vec<Utxo>,
+}
+
+// splits freshly seen utxos into the ones carrying runes and the ones the
+// indexer hasn't reached yet. everything else is plain bitcoin. without an
+// indexer configured nothing can be told apart, so all of them stay pending
+// rather than risk spending runes as fees
+pub async fn classify_utxos(utxos: Vec<Utxo>) -> Result<Classification, String> {
+    let mut classification = Classification::default();
+    let indexer = match indexer() {
+        None => {
+            classification.pending = utxos;
+            return Ok(classification);
+        }
+        Some(indexer) => indexer,
+    };
+    if utxos.is_empty() {
+        return Ok(classification);
+    }
+
+    let indexed_height = get_latest_block(indexer).await?;
+    let (indexed, pending): (Vec<Utxo>, Vec<Utxo>) = utxos
+        .into_iter()
+        .partition(|utxo| utxo.height <= indexed_height);
+    classification.pending = pending;
+
+    for chunk in indexed.chunks(MAX_OUTPOINTS_PER_QUERY) {
+        let outpoints = chunk
+            .iter()
+            .map(|utxo| {
+                format!(
+                    "{}:{}",
+                    slice_to_txid(&utxo.outpoint.txid),
+                    utxo.outpoint.vout
+                )
+            })
+            .collect();
+        let balances = get_rune_balances_for_outputs(indexer, outpoints).await?;
+
+        for (utxo, balances) in chunk.iter().zip(balances) {
+            for balance in balances.unwrap_or_default() {
+                let runeid = parse_rune_id(&balance.rune_id)?;
+                classification.runic.push((
+                    runeid,
+                    RunicUtxo {
+                        balance: balance.amount,
+                        utxo: utxo.clone(),
+                        recorded_at: None,
+                    },
+                ));
+            }
+        }
+    }
+
+    Ok(classification)
+}
@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+
+use bitcoin::{hashes::Hash, Address, Transaction, Txid};
+use ic_cdk::api::management_canister::bitcoin::{Outpoint, Utxo};
+use ordinals::{Artifact, Runestone};
+
+use crate::{
+    chain::btc::to_network,
+    state::{read_config, utxo_manager::RunicUtxo, write_utxo_manager},
+};
+
+use super::RuneId;
+
+#[derive(Default)]
+pub struct RuneAllocation {
+    // balances of each output, indexed by vout
+    pub outputs: Vec<HashMap<RuneId, u128>>,
+    pub burned: HashMap<RuneId, u128>,
+}
+
+// applies the runes protocol to `txn` the same way ord does. `inputs` are the
+// rune balances of all spent outputs combined, `etched` is the id the etching
+// of this transaction gets (if known) and `mint_amount` the amount a mint of
+// the given rune yields at this point, `None` when it isn't mintable
+pub fn index_transaction<F>(
+    txn: &Transaction,
+    inputs: HashMap<RuneId, u128>,
+    etched: Option<RuneId>,
+    mint_amount: F,
+) -> RuneAllocation
+where
+    F: Fn(&RuneId) -> Option<u128>,
+{
+    let mut unallocated = inputs;
+    let mut allocated: Vec<HashMap<RuneId, u128>> = vec![HashMap::new(); txn.output.len()];
+    let mut burned: HashMap<RuneId, u128> = HashMap::new();
+
+    let artifact = Runestone::decipher(txn);
+
+    if let Some(artifact) = &artifact {
+        if let Some(id) = artifact.mint() {
+            let id = RuneId::from(id);
+            if let Some(amount) = mint_amount(&id) {
+                *unallocated.entry(id).or_default() += amount;
+            }
+        }
+
+        if let Artifact::Runestone(runestone) = artifact {
+            let etched = etched.filter(|_| runestone.etching.is_some());
+            if let (Some(id), Some(etching)) = (etched, &runestone.etching) {
+                *unallocated.entry(id).or_default() += etching.premine.unwrap_or_default();
+            }
+
+            for edict in runestone.edicts.iter() {
+                let output = edict.output as usize;
+                // the default id refers to the rune etched in this transaction
+                let id = if edict.id == ordinals::RuneId::default() {
+                    match etched {
+                        None => continue,
+                        Some(id) => id,
+                    }
+                } else {
+                    RuneId::from(edict.id)
+                };
+                let Some(balance) = unallocated.get_mut(&id) else {
+                    continue;
+                };
+
+                let mut allocate = |balance: &mut u128, amount: u128, output: usize| {
+                    if amount > 0 {
+                        *balance -= amount;
+                        *allocated[output].entry(id).or_default() += amount;
+                    }
+                };
+
+                if output == txn.output.len() {
+                    let destinations: Vec<usize> = txn
+                        .output
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(vout, txout)| {
+                            (!txout.script_pubkey.is_op_return()).then_some(vout)
+                        })
+                        .collect();
+                    if destinations.is_empty() {
+                        continue;
+                    }
+                    if edict.amount == 0 {
+                        // split the whole balance evenly, the first outputs take the remainder
+                        let amount = *balance / destinations.len() as u128;
+                        let remainder = (*balance % destinations.len() as u128) as usize;
+                        for (index, output) in destinations.into_iter().enumerate() {
+                            let amount = if index < remainder {
+                                amount + 1
+                            } else {
+                                amount
+                            };
+                            allocate(balance, amount, output);
+                        }
+                    } else {
+                        for output in destinations {
+                            let amount = edict.amount.min(*balance);
+                            allocate(balance, amount, output);
+                        }
+                    }
+                } else {
+                    let amount = if edict.amount == 0 {
+                        *balance
+                    } else {
+                        edict.amount.min(*balance)
+                    };
+                    allocate(balance, amount, output);
+                }
+            }
+        }
+    }
+
+    match artifact {
+        // everything a cenotaph touches is burned
+        Some(Artifact::Cenotaph(_)) => {
+            for (id, balance) in unallocated {
+                *burned.entry(id).or_default() += balance;
+            }
+        }
+        artifact => {
+            let pointer = match artifact {
+                Some(Artifact::Runestone(runestone)) => runestone.pointer,
+                _ => None,
+            };
+            // leftovers go to the pointer, or the first non OP_RETURN output
+            let vout = pointer.map(|pointer| pointer as usize).or_else(|| {
+                txn.output
+                    .iter()
+                    .position(|txout| !txout.script_pubkey.is_op_return())
+            });
+            for (id, balance) in unallocated {
+                if balance == 0 {
+                    continue;
+                }
+                match vout {
+                    Some(vout) => *allocated[vout].entry(id).or_default() += balance,
+                    None => *burned.entry(id).or_default() += balance,
+                }
+            }
+        }
+    }
+
+    // runes sent to an OP_RETURN are burned as well
+    for (vout, balances) in allocated.iter_mut().enumerate() {
+        if txn.output[vout].script_pubkey.is_op_return() {
+            for (id, balance) in balances.drain() {
+                *burned.entry(id).or_default() += balance;
+            }
+        }
+    }
+
+    RuneAllocation {
+        outputs: allocated,
+        burned,
+    }
+}
+
+// records the runic outputs of a transaction broadcast by this canister, so
+// balances are known before the indexer catches up. outputs stay at height 0
+// until the syncer sees them confirmed
+pub fn record_runic_outputs(txn: &Transaction, allocation: &RuneAllocation) {
+    let network = read_config(|config| to_network(config.bitcoin_network()));
+    let txid = txn.compute_txid().to_byte_array().to_vec();
+
+    for (vout, balances) in allocation.outputs.iter().enumerate() {
+        if balances.is_empty() {
+            continue;
+        }
+        let txout = &txn.output[vout];
+        let addr = match Address::from_script(&txout.script_pubkey, network) {
+            Err(_) => continue,
+            Ok(addr) => addr.to_string(),
+        };
+        let utxo = Utxo {
+            outpoint: Outpoint {
+                txid: txid.clone(),
+                vout: vout as u32,
+            },
+            value: txout.value.to_sat(),
+            height: 0,
+        };
+        write_utxo_manager(|manager| {
+            if !manager.is_tracked(&addr) {
+                return;
+            }
+            for (runeid, balance) in balances {
+                manager.record_runic_utxos(
+                    &addr,
+                    *runeid,
+                    vec![RunicUtxo {
+                        balance: *balance,
+                        utxo: utxo.clone(),
+                        recorded_at: None,
+                    }],
+                );
+            }
+        });
+    }
+}
+
+// moves the runic outputs recorded for `previous` to its replacement `txn`
+pub fn replace_runic_outputs(previous: &Txid, txn: &Transaction) {
+    let network = read_config(|config| to_network(config.bitcoin_network()));
+    let txid = txn.compute_txid().to_byte_array().to_vec();
+
+    let mut outputs: HashMap<String, Vec<Utxo>> = HashMap::new();
+    for (vout, txout) in txn.output.iter().enumerate() {
+        let addr = match Address::from_script(&txout.script_pubkey, network) {
+            Err(_) => continue,
+            Ok(addr) => addr.to_string(),
+        };
+        outputs.entry(addr).or_default().push(Utxo {
+            outpoint: Outpoint {
+                txid: txid.clone(),
+                vout: vout as u32,
+            },
+            value: txout.value.to_sat(),
+            height: 0,
+        });
+    }
+    write_utxo_manager(|manager| {
+        for (addr, utxos) in outputs {
+            manager.replace_runic_outputs(&addr, &previous.to_byte_array(), &utxos);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{
+        absolute::LockTime, transaction::Version, Amount, ScriptBuf, Transaction, TxOut,
+    };
+    use ordinals::{Edict, Runestone};
+
+    use super::*;
+
+    const RUNE: RuneId = RuneId {
+        block: 840_000,
+        tx: 1,
+    };
+
+    fn txn(runestone: Runestone, outputs: usize) -> Transaction {
+        let mut output: Vec<TxOut> = (0..outputs)
+            .map(|_| TxOut {
+                value: Amount::from_sat(546),
+                script_pubkey: ScriptBuf::from_bytes(vec![0x51]),
+            })
+            .collect();
+        output.push(TxOut {
+            value: Amount::ZERO,
+            script_pubkey: runestone.encipher(),
+        });
+        Transaction {
+            version: Version(2),
+            lock_time: LockTime::ZERO,
+            input: vec![],
+            output,
+        }
+    }
+
+    fn edict(amount: u128, output: u32) -> Edict {
+        Edict {
+            id: RUNE.into(),
+            amount,
+            output,
+        }
+    }
+
+    fn balance(allocation: &RuneAllocation, vout: usize) -> Option<u128> {
+        allocation.outputs[vout].get(&RUNE).copied()
+    }
+
+    fn index(txn: &Transaction, balance: u128) -> RuneAllocation {
+        index_transaction(txn, HashMap::from([(RUNE, balance)]), None, |_| None)
+    }
+
+    #[test]
+    fn leftovers_go_to_the_first_output() {
+        let runestone = Runestone {
+            edicts: vec![edict(300, 1)],
+            ..Default::default()
+        };
+        let allocation = index(&txn(runestone, 2), 1_000);
+
+        assert_eq!(balance(&allocation, 0), Some(700));
+        assert_eq!(balance(&allocation, 1), Some(300));
+        assert!(allocation.burned.is_empty());
+    }
+
+    #[test]
+    fn leftovers_go_to_the_pointer() {
+        let runestone = Runestone {
+            edicts: vec![edict(300, 0)],
+            pointer: Some(1),
+            ..Default::default()
+        };
+        let allocation = index(&txn(runestone, 2), 1_000);
+
+        assert_eq!(balance(&allocation, 0), Some(300));
+        assert_eq!(balance(&allocation, 1), Some(700));
+    }
+
+    #[test]
+    fn edict_amount_is_capped_by_the_balance() {
+        let runestone = Runestone {
+            edicts: vec![edict(800, 0), edict(800, 1)],
+            ..Default::default()
+        };
+        let allocation = index(&txn(runestone, 2), 1_000);
+
+        assert_eq!(balance(&allocation, 0), Some(800));
+        assert_eq!(balance(&allocation, 1), Some(200));
+    }
+
+    #[test]
+    fn zero_amount_to_all_outputs_splits_evenly() {
+        // the output count targets every output but the OP_RETURN
+        let runestone = Runestone {
+            edicts: vec![edict(0, 3)],
+            ..Default::default()
+        };
+        let allocation = index(&txn(runestone, 2), 1_001);
+
+        assert_eq!(balance(&allocation, 0), Some(501));
+        assert_eq!(balance(&allocation, 1), Some(500));
+        assert_eq!(balance(&allocation, 2), None);
+    }
+
+    #[test]
+    fn edict_to_op_return_burns() {
+        let runestone = Runestone {
+            edicts: vec![edict(400, 1)],
+            ..Default::default()
+        };
+        let allocation = index(&txn(runestone, 1), 1_000);
+
+        assert_eq!(balance(&allocation, 0), Some(600));
+        assert_eq!(allocation.burned.get(&RUNE).copied(), Some(400));
+    }
+
+    #[test]
+    fn cenotaph_burns_everything() {
+        // an edict past the last output makes the runestone a cenotaph
+        let runestone = Runestone {
+            edicts: vec![edict(300, 5)],
+            ..Default::default()
+        };
+        let allocation = index(&txn(runestone, 2), 1_000);
+
+        assert_eq!(balance(&allocation, 0), None);
+        assert_eq!(balance(&allocation, 1), None);
+        assert_eq!(allocation.burned.get(&RUNE).copied(), Some(1_000));
+    }
+
+    #[test]
+    fn mint_is_allocated_with_the_inputs() {
+        let runestone = Runestone {
+            mint: Some(RUNE.into()),
+            ..Default::default()
+        };
+        let allocation = index_transaction(&txn(runestone, 1), HashMap::new(), None, |id| {
+            (*id == RUNE).then_some(50)
+        });
+
+        assert_eq!(balance(&allocation, 0), Some(50));
+        assert!(allocation.burned.is_empty());
+    }
+}
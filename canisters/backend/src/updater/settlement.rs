@@ -0,0 +1,763 @@
+use std::{cell::RefCell, collections::HashMap, collections::HashSet};
+
+use bitcoin::{
+    absolute::LockTime, hashes::Hash, transaction::Version, Address, Amount, ScriptBuf,
+    Transaction, TxOut,
+};
+use candid::Principal;
+use ic_cdk::api::management_canister::bitcoin::{Outpoint, Utxo};
+use icrc_ledger_types::icrc1::account::Account;
+
+use crate::{
+    addresses::Addresses,
+    chain::{
+        btc::{
+            address_validation, get_fee_per_vbyte,
+            runestone::distribute::distribution_runestone,
+            send_transaction,
+            signer::ecdsa::{ecdsa_sign_transaction, mock_ecdsa_signature},
+            transaction::{transaction_fee, utxos_to_inputs},
+            DUST_THRESHOLD,
+        },
+        icrc::Ledger,
+    },
+    guard::OnDrop,
+    state::{
+        launch_manager::{Contribution, Launch, LaunchStatus},
+        read_config, read_launch_manager, read_user_manager, read_utxo_manager,
+        txn_manager::SpentInput,
+        utxo_manager::{select_utxos, PendingSpend, SelectionStrategy},
+        write_launch_manager, write_utxo_manager,
+    },
+    token_type::TokenType,
+    txn_handler::TransactionType,
+};
+
+use super::{
+    rune_indexer::{index_transaction, record_runic_outputs},
+    txn_watcher::register_transaction,
+    utxo_syncer::sync_address,
+    RuneId,
+};
+
+const MAX_OUTPUTS_PER_TRANSACTION: usize = 200;
+// stays below the default mempool limit of 25 unconfirmed ancestors
+const MAX_CHAINED_TRANSACTIONS: usize = 24;
+
+thread_local! {
+    // launches with a settlement in flight, so overlapping ticks can't spend
+    // the same escrow utxos twice
+    static SETTLING: RefCell<HashSet<u128>> = RefCell::default();
+}
+
+// `None` while another tick settles the launch
+fn claim_settlement(launch_id: u128) -> Option<OnDrop<u128, impl FnOnce(u128)>> {
+    SETTLING
+        .with_borrow_mut(|settling| settling.insert(launch_id))
+        .then(|| {
+            OnDrop::new(launch_id, |launch_id| {
+                SETTLING.with_borrow_mut(|settling| settling.remove(&launch_id));
+            })
+        })
+}
+
+// funds raised by a launch sit in the p2wpkh escrow, its premine in the p2pkh
+// vault of the same account. distributions are funded from the escrow. when the
+// raise is held on a ledger there's no bitcoin in the escrow, so the postage and
+// fees of the distribution come out of the creator's deposit address instead.
+// `creator` is where the creator gets paid
+struct LaunchWallet {
+    addresses: Addresses,
+    escrow: Address,
+    vault: Address,
+    creator: Address,
+    funder: Address,
+    funder_account: Account,
+}
+
+impl LaunchWallet {
+    fn new(launch: &Launch) -> Result<Self, String> {
+        let addresses = Addresses::for_launch(launch.launch_id);
+        let escrow = address_validation(&addresses.bitcoin)?;
+        let vault = address_validation(&addresses.bitcoin_legacy)?;
+        let creator = payout_address(&launch.created_by)?;
+        let (funder, funder_account) = match launch.raise_in {
+            TokenType::Bitcoin => (escrow.clone(), addresses.icrc1),
+            _ => {
+                let creator_addresses = Addresses::from(&launch.created_by);
+                (
+                    address_validation(&creator_addresses.bitcoin)?,
+                    creator_addresses.icrc1,
+                )
+            }
+        };
+        Ok(Self {
+            addresses,
+            escrow,
+            vault,
+            creator,
+            funder,
+            funder_account,
+        })
+    }
+
+    // the vault is always the first input when it's spent
+    fn estimate_fee(&self, txn: &Transaction, spends_vault: bool, fee_per_vbytes: u64) -> u64 {
+        let mut mocked = mock_ecdsa_signature(txn, &self.funder);
+        if spends_vault {
+            mocked.input[0] = mock_ecdsa_signature(txn, &self.vault).input.remove(0);
+        }
+        transaction_fee(&mocked, fee_per_vbytes).to_sat()
+    }
+
+    // `utxos` are the funder's
+    async fn sign(&self, txn: Transaction, premine: Option<&Utxo>, utxos: &[Utxo]) -> Transaction {
+        let account = self.addresses.icrc1;
+        let txn = match premine {
+            None => txn,
+            Some(premine) => {
+                ecdsa_sign_transaction(&account, &self.vault, txn, std::slice::from_ref(premine))
+                    .await
+            }
+        };
+        ecdsa_sign_transaction(&self.funder_account, &self.funder, txn, utxos).await
+    }
+
+    // the inputs of a transaction signed by `sign`, for fee bumps
+    fn spent_inputs(&self, premine: Option<&Utxo>, utxos: &[Utxo]) -> Vec<SpentInput> {
+        let vault = premine.map(|premine| SpentInput {
+            utxo: premine.clone(),
+            address: self.addresses.bitcoin_legacy.clone(),
+            account: Some(self.addresses.icrc1),
+        });
+        vault
+            .into_iter()
+            .chain(utxos.iter().map(|utxo| SpentInput {
+                utxo: utxo.clone(),
+                address: self.funder_addr(),
+                account: Some(self.funder_account),
+            }))
+            .collect()
+    }
+
+    fn funder_addr(&self) -> String {
+        self.funder.to_string()
+    }
+
+    fn funder_shortfall(&self) -> String {
+        if self.funder == self.escrow {
+            return String::from("escrow doesn't cover the distribution");
+        }
+        format!(
+            "creator's deposit address {} doesn't hold enough bitcoin for the postage and fees of the distribution",
+            self.funder
+        )
+    }
+}
+
+fn build_transaction(inputs: &[Utxo], output: Vec<TxOut>) -> Transaction {
+    Transaction {
+        input: utxos_to_inputs(inputs),
+        output,
+        version: Version(2),
+        lock_time: LockTime::ZERO,
+    }
+}
+
+// the wallet `principal` signed in with, or its deposit address when it never
+// did. a bound address that doesn't suit the network falls back as well
+fn payout_address(principal: &Principal) -> Result<Address, String> {
+    let bound = read_user_manager(|manager| manager.get_user(principal).btc_address);
+    match bound.and_then(|addr| address_validation(&addr).ok()) {
+        Some(addr) => Ok(addr),
+        None => address_validation(&Addresses::from(principal).bitcoin),
+    }
+}
+
+fn payout_script(principal: &Principal) -> Result<ScriptBuf, String> {
+    payout_address(principal).map(|addr| addr.script_pubkey())
+}
+
+// ordered so batches come out the same on every attempt
+fn sorted_contributions(launch_id: u128) -> Vec<Contribution> {
+    let mut contributions: Vec<Contribution> =
+        read_launch_manager(|manager| manager.get_contributions(launch_id))
+            .0
+            .into_values()
+            .collect();
+    contributions.sort_by_key(|contribution| contribution.contributor);
+    contributions
+}
+
+pub async fn settle_launches() {
+    let due = read_launch_manager(|manager| {
+        let mut due = manager.launches_with_status(LaunchStatus::Succeeded);
+        due.extend(manager.launches_with_status(LaunchStatus::Failed));
+        due
+    });
+
+    for launch in due {
+        let Some(_claim) = claim_settlement(launch.launch_id) else {
+            continue;
+        };
+        // every step records its progress once broadcast, anything failing
+        // is picked up again on the next tick and kept on the launch meanwhile
+        let launch_id = launch.launch_id;
+        let result = match launch.status {
+            LaunchStatus::Succeeded => settle_success(launch).await,
+            _ => settle_failure(launch).await,
+        };
+        write_launch_manager(|manager| manager.record_result(launch_id, &result));
+    }
+}
+
+// deposits have to be confirmed and earlier settlement transactions mined
+// before the funder can be spent from again
+async fn escrow_ready(wallet: &LaunchWallet) -> Result<bool, String> {
+    let funder = wallet.funder_addr();
+    sync_address(&funder).await?;
+    Ok(read_utxo_manager(|manager| {
+        !manager.has_pending_spends(&funder)
+    }))
+}
+
+async fn settle_failure(launch: Launch) -> Result<(), String> {
+    let wallet = LaunchWallet::new(&launch)?;
+    if let Some(ledger) = launch.raise_in.ledger()? {
+        // only the premine is left to return on bitcoin afterwards
+        refund_on_ledger(&launch, wallet.addresses.icrc1, &ledger).await?;
+    }
+    if !escrow_ready(&wallet).await? {
+        return Ok(());
+    }
+    let fee_per_vbytes = get_fee_per_vbyte().await;
+
+    let pending: Vec<Contribution> = sorted_contributions(launch.launch_id)
+        .into_iter()
+        .filter(|contribution| contribution.refund_txid.is_none())
+        .collect();
+    if pending.is_empty() && launch.premine_utxo.is_none() {
+        return write_launch_manager(|manager| {
+            manager.transition(launch.launch_id, LaunchStatus::Refunded)
+        });
+    }
+    refund(&launch, &wallet, pending, fee_per_vbytes).await
+}
+
+// refunds a batch of contributions, sending the premine back to the creator
+// with the first one. the fee is split between the refunds, or taken from the
+// premine postage when nothing is left to refund
+async fn refund(
+    launch: &Launch,
+    wallet: &LaunchWallet,
+    pending: Vec<Contribution>,
+    fee_per_vbytes: u64,
+) -> Result<(), String> {
+    let utxos = read_utxo_manager(|manager| manager.get_bitcoin_utxos(&wallet.addresses.bitcoin));
+    let balance: u64 = utxos.iter().map(|utxo| utxo.value).sum();
+    let outstanding: u64 = pending.iter().map(|contribution| contribution.amount).sum();
+    if balance < outstanding {
+        // deposits are still confirming
+        return Ok(());
+    }
+
+    let mut batch = pending
+        .into_iter()
+        .take(MAX_OUTPUTS_PER_TRANSACTION)
+        .map(|contribution| Ok((payout_script(&contribution.contributor)?, contribution)))
+        .collect::<Result<Vec<(ScriptBuf, Contribution)>, String>>()?;
+    let premine = launch.premine_utxo.clone();
+
+    let (inputs, output, fee_output) = loop {
+        let mut inputs: Vec<Utxo> = premine.iter().cloned().collect();
+        if !batch.is_empty() {
+            inputs.extend(utxos.iter().cloned());
+        }
+        // runes without a runestone go to the first output, which is the creator's
+        let mut postage = premine.as_ref().map(|premine| TxOut {
+            script_pubkey: wallet.creator.script_pubkey(),
+            value: Amount::from_sat(premine.value),
+        });
+        let mut refunds: Vec<TxOut> = batch
+            .iter()
+            .map(|(script_pubkey, contribution)| TxOut {
+                script_pubkey: script_pubkey.clone(),
+                value: Amount::from_sat(contribution.amount),
+            })
+            .collect();
+        let refunded: u64 = batch
+            .iter()
+            .map(|(_, contribution)| contribution.amount)
+            .sum();
+        let change = (!batch.is_empty())
+            .then(|| balance - refunded)
+            .filter(|change| *change > DUST_THRESHOLD)
+            .map(|change| TxOut {
+                script_pubkey: wallet.escrow.script_pubkey(),
+                value: Amount::from_sat(change),
+            });
+        let outputs = |postage: &Option<TxOut>, refunds: &[TxOut]| {
+            postage
+                .iter()
+                .chain(refunds.iter())
+                .chain(change.iter())
+                .cloned()
+                .collect::<Vec<TxOut>>()
+        };
+        let fee = wallet.estimate_fee(
+            &build_transaction(&inputs, outputs(&postage, &refunds)),
+            premine.is_some(),
+            fee_per_vbytes,
+        );
+
+        if batch.is_empty() {
+            let Some(txout) = postage.as_mut() else {
+                return Err(String::from("refunds don't cover their share of the fee"));
+            };
+            txout.value = txout
+                .value
+                .checked_sub(Amount::from_sat(fee))
+                .filter(|value| *value >= txout.script_pubkey.minimal_non_dust())
+                .ok_or_else(|| String::from("premine postage doesn't cover the fee"))?;
+            // a bump comes out of the premine postage as well
+            break (inputs, outputs(&postage, &refunds), Some(0));
+        }
+        let share = Amount::from_sat(fee.div_ceil(batch.len() as u64));
+        // refunds the fee would leave as dust wait in the escrow change for a
+        // later batch, the fee is split again between the others
+        let before = batch.len();
+        batch.retain(|(script_pubkey, contribution)| {
+            Amount::from_sat(contribution.amount)
+                .checked_sub(share)
+                .is_some_and(|value| value >= script_pubkey.minimal_non_dust())
+        });
+        if batch.len() == before {
+            for refund in refunds.iter_mut() {
+                refund.value -= share;
+            }
+            let output = outputs(&postage, &refunds);
+            // a bump comes out of the escrow change, the refunds are left alone
+            let fee_output = change.as_ref().map(|_| output.len() as u32 - 1);
+            break (inputs, output, fee_output);
+        }
+    };
+
+    let spent: Vec<Utxo> = inputs
+        .iter()
+        .filter(|&utxo| Some(utxo) != premine.as_ref())
+        .cloned()
+        .collect();
+    let txn = build_transaction(&inputs, output);
+    // taken before signing so nothing else spends them meanwhile, they're
+    // given back when the refund doesn't go out
+    write_utxo_manager(|manager| manager.remove_bitcoin_utxos(&wallet.addresses.bitcoin, &spent));
+    let spend = PendingSpend::new(&wallet.addresses.bitcoin, &spent);
+    let txn = wallet.sign(txn, premine.as_ref(), &spent).await;
+    send_transaction(&txn).await?;
+    spend.broadcast();
+    let txid = txn.compute_txid().to_string();
+    register_transaction(
+        TransactionType::Refund {
+            launch_id: launch.launch_id,
+        },
+        &txn,
+        wallet.spent_inputs(premine.as_ref(), &spent),
+        fee_output,
+    );
+
+    write_launch_manager(|manager| {
+        for (_, contribution) in batch.iter() {
+            manager.update_contribution(launch.launch_id, &contribution.contributor, |c| {
+                c.refund_txid.replace(txid.clone())
+            })?;
+        }
+        manager.update_launch(launch.launch_id, |launch| launch.premine_utxo = None)
+    })
+}
+
+// refunds contributions from the `escrow` account on the ledger the raise was
+// made on, less the ledger fee. each refund is recorded as soon as it's made
+async fn refund_on_ledger<L: Ledger>(
+    launch: &Launch,
+    escrow: Account,
+    ledger: &L,
+) -> Result<(), String> {
+    let fee = ledger.fee().await?;
+    let pending = sorted_contributions(launch.launch_id)
+        .into_iter()
+        .filter(|contribution| contribution.refund_txid.is_none());
+
+    for contribution in pending {
+        let block_index = ledger
+            .transfer(
+                escrow.subaccount,
+                Account::from(contribution.contributor),
+                contribution.amount.saturating_sub(fee),
+            )
+            .await?;
+        write_launch_manager(|manager| {
+            manager.update_contribution(launch.launch_id, &contribution.contributor, |c| {
+                c.refund_txid.replace(block_index.to_string())
+            })
+        })?;
+    }
+    Ok(())
+}
+
+async fn settle_success(launch: Launch) -> Result<(), String> {
+    let wallet = LaunchWallet::new(&launch)?;
+    if !escrow_ready(&wallet).await? {
+        return Ok(());
+    }
+    let fee_per_vbytes = get_fee_per_vbyte().await;
+    let contributions = sorted_contributions(launch.launch_id);
+
+    if launch.premine_utxo.is_some() {
+        if contributions.is_empty() {
+            // nothing was sold, the premine goes back to the creator
+            return refund(&launch, &wallet, vec![], fee_per_vbytes).await;
+        }
+        return distribute(&launch, &wallet, contributions, fee_per_vbytes).await;
+    }
+    match launch.raise_in.ledger()? {
+        None => payout(&launch, &wallet, fee_per_vbytes).await,
+        Some(ledger) => payout_on_ledger(&launch, wallet.addresses.icrc1, &ledger).await,
+    }
+}
+
+struct DistributionBatch {
+    txn: Transaction,
+    spent: Vec<Utxo>,
+    packed: usize,
+    last: bool,
+}
+
+// sends the allocations of as many contributors as fit in one runestone. the
+// rest of the premine moves to a new vault output, or to the creator with the
+// last batch. postage and fee are paid by the funder
+fn build_distribution_batch(
+    wallet: &LaunchWallet,
+    rune_id: RuneId,
+    premine: &Utxo,
+    pending: &[Contribution],
+    available: &[Utxo],
+    fee_per_vbytes: u64,
+) -> Result<DistributionBatch, String> {
+    let allocations: Vec<u128> = pending
+        .iter()
+        .take(MAX_OUTPUTS_PER_TRANSACTION)
+        .map(|contribution| contribution.allocation)
+        .collect();
+    let size_limit = read_config(|config| config.runestone_size_limit());
+    let (runestone, packed) = distribution_runestone(rune_id.into(), &allocations, size_limit);
+    if packed == 0 && !pending.is_empty() {
+        return Err(String::from(
+            "runestone size limit doesn't fit a single edict",
+        ));
+    }
+    let last = packed == pending.len();
+
+    let mut output = pending[..packed]
+        .iter()
+        .map(|contribution| {
+            let script_pubkey = payout_script(&contribution.contributor)?;
+            Ok(TxOut {
+                value: script_pubkey.minimal_non_dust(),
+                script_pubkey,
+            })
+        })
+        .collect::<Result<Vec<TxOut>, String>>()?;
+    let postage: u64 = output.iter().map(|txout| txout.value.to_sat()).sum();
+    let remainder = if last { &wallet.creator } else { &wallet.vault };
+    output.push(TxOut {
+        script_pubkey: remainder.script_pubkey(),
+        value: Amount::from_sat(premine.value),
+    });
+    output.push(TxOut {
+        script_pubkey: runestone,
+        value: Amount::ZERO,
+    });
+
+    let mut fee = 0;
+    loop {
+        let target = postage + fee;
+        let selected = select_utxos(available, target, SelectionStrategy::LargestFirst)
+            .ok_or_else(|| wallet.funder_shortfall())?;
+        let mut output = output.clone();
+        let change = selected.iter().map(|utxo| utxo.value).sum::<u64>() - target;
+        if change > DUST_THRESHOLD {
+            output.push(TxOut {
+                script_pubkey: wallet.funder.script_pubkey(),
+                value: Amount::from_sat(change),
+            });
+        }
+        let mut inputs = vec![premine.clone()];
+        inputs.extend(selected.iter().cloned());
+        let txn = build_transaction(&inputs, output);
+        let required_fee = wallet.estimate_fee(&txn, true, fee_per_vbytes);
+        if required_fee <= fee {
+            return Ok(DistributionBatch {
+                txn,
+                spent: selected,
+                packed,
+                last,
+            });
+        }
+        fee = required_fee;
+    }
+}
+
+// distributes batch after batch, each one spending the vault output and the
+// funder's change of the one before without waiting for confirmations. the
+// chain is kept under the mempool's ancestor limit, anything left over
+// continues once it's mined. for raises held on a ledger the funder is the
+// creator's deposit address, which has to hold enough bitcoin for the postage
+// and fees
+async fn distribute(
+    launch: &Launch,
+    wallet: &LaunchWallet,
+    contributions: Vec<Contribution>,
+    fee_per_vbytes: u64,
+) -> Result<(), String> {
+    let Some(rune_id) = launch.rune_id else {
+        return Err(String::from("rune id of the etching isn't known"));
+    };
+    let mut premine = launch
+        .premine_utxo
+        .clone()
+        .expect("premine should be unspent");
+    let mut available =
+        read_utxo_manager(|manager| manager.get_bitcoin_utxos(&wallet.funder_addr()));
+    let balance: u64 = available.iter().map(|utxo| utxo.value).sum();
+    let first_batch = contributions
+        .iter()
+        .all(|contribution| contribution.distribution_txid.is_none());
+    if launch.raise_in == TokenType::Bitcoin && first_batch && balance < launch.raised {
+        // deposits are still confirming
+        return Ok(());
+    }
+
+    let mut distributed: u128 = contributions
+        .iter()
+        .filter(|contribution| contribution.distribution_txid.is_some())
+        .map(|contribution| contribution.allocation)
+        .sum();
+    let mut pending: Vec<Contribution> = contributions
+        .into_iter()
+        .filter(|contribution| contribution.distribution_txid.is_none())
+        .filter(|contribution| contribution.allocation > 0)
+        .collect();
+
+    for _ in 0..MAX_CHAINED_TRANSACTIONS {
+        let DistributionBatch {
+            txn,
+            spent,
+            packed,
+            last,
+        } = build_distribution_batch(
+            wallet,
+            rune_id,
+            &premine,
+            &pending,
+            &available,
+            fee_per_vbytes,
+        )?;
+        write_utxo_manager(|manager| manager.remove_bitcoin_utxos(&wallet.funder_addr(), &spent));
+        let spend = PendingSpend::new(&wallet.funder_addr(), &spent);
+        let txn = wallet.sign(txn, Some(&premine), &spent).await;
+        send_transaction(&txn).await?;
+        spend.broadcast();
+        let txid = txn.compute_txid();
+        // a bump comes out of the funder's change, when there is any
+        let fee_output = (txn.output.len() > packed + 2).then_some(packed as u32 + 2);
+        register_transaction(
+            TransactionType::Distribution {
+                launch_id: launch.launch_id,
+            },
+            &txn,
+            wallet.spent_inputs(Some(&premine), &spent),
+            fee_output,
+        );
+
+        let allocation = index_transaction(
+            &txn,
+            HashMap::from([(rune_id, launch.total_supply - distributed)]),
+            None,
+            |_| None,
+        );
+        record_runic_outputs(&txn, &allocation);
+
+        let output = |vout: usize| {
+            txn.output.get(vout).map(|txout| Utxo {
+                outpoint: Outpoint {
+                    txid: txid.to_byte_array().to_vec(),
+                    vout: vout as u32,
+                },
+                value: txout.value.to_sat(),
+                height: 0,
+            })
+        };
+        // outputs are the recipients, the remainder, the runestone and the change
+        let vault_utxo = if last { None } else { output(packed) };
+        available.retain(|utxo| !spent.contains(utxo));
+        available.extend(output(packed + 2));
+
+        let batch: Vec<Contribution> = pending.drain(..packed).collect();
+        distributed += batch
+            .iter()
+            .map(|contribution| contribution.allocation)
+            .sum::<u128>();
+        write_launch_manager(|manager| {
+            for contribution in batch.iter() {
+                manager.update_contribution(launch.launch_id, &contribution.contributor, |c| {
+                    c.distribution_txid.replace(txid.to_string())
+                })?;
+            }
+            manager.update_launch(launch.launch_id, |launch| {
+                launch.premine_utxo = vault_utxo.clone()
+            })
+        })?;
+
+        match vault_utxo {
+            None => break,
+            Some(vault_utxo) => premine = vault_utxo,
+        }
+    }
+    Ok(())
+}
+
+// sweeps what is left in the escrow to the creator, minus the commission
+async fn payout(launch: &Launch, wallet: &LaunchWallet, fee_per_vbytes: u64) -> Result<(), String> {
+    let utxos = read_utxo_manager(|manager| manager.get_bitcoin_utxos(&wallet.addresses.bitcoin));
+    if utxos.is_empty() {
+        return write_launch_manager(|manager| {
+            manager.transition(launch.launch_id, LaunchStatus::Distributed)
+        });
+    }
+    let total: u64 = utxos.iter().map(|utxo| utxo.value).sum();
+    let (commission_receiver, commission_bps) =
+        read_config(|config| (config.commission_receiver, config.commission_bps()));
+
+    let mut output = vec![TxOut {
+        script_pubkey: wallet.creator.script_pubkey(),
+        value: Amount::from_sat(total),
+    }];
+    if let Some(receiver) = commission_receiver {
+        let commission =
+            ((launch.raised as u128 * commission_bps as u128) / 10_000).min(total as u128) as u64;
+        if commission > DUST_THRESHOLD {
+            output[0].value -= Amount::from_sat(commission);
+            output.push(TxOut {
+                script_pubkey: payout_script(&receiver)?,
+                value: Amount::from_sat(commission),
+            });
+        }
+    }
+    let fee = wallet.estimate_fee(
+        &build_transaction(&utxos, output.clone()),
+        false,
+        fee_per_vbytes,
+    );
+    output[0].value = output[0]
+        .value
+        .checked_sub(Amount::from_sat(fee))
+        .filter(|value| *value >= output[0].script_pubkey.minimal_non_dust())
+        .ok_or_else(|| String::from("escrow doesn't cover the payout fee"))?;
+
+    write_utxo_manager(|manager| manager.remove_bitcoin_utxos(&wallet.addresses.bitcoin, &utxos));
+    let spend = PendingSpend::new(&wallet.addresses.bitcoin, &utxos);
+    let txn = wallet
+        .sign(build_transaction(&utxos, output), None, &utxos)
+        .await;
+    send_transaction(&txn).await?;
+    spend.broadcast();
+    let txid = txn.compute_txid().to_string();
+    // a bump comes out of the creator's share
+    register_transaction(
+        TransactionType::Payout {
+            launch_id: launch.launch_id,
+        },
+        &txn,
+        wallet.spent_inputs(None, &utxos),
+        Some(0),
+    );
+
+    write_launch_manager(|manager| {
+        manager.update_launch(launch.launch_id, |launch| launch.payout_txid.replace(txid))?;
+        manager.transition(launch.launch_id, LaunchStatus::Distributed)
+    })
+}
+
+// pays the commission and then the creator from the `escrow` account on the
+// ledger the raise was made on. deposits arrive without fees, so an escrow
+// balance below what was raised means the commission went out on an earlier
+// attempt
+async fn payout_on_ledger<L: Ledger>(
+    launch: &Launch,
+    escrow: Account,
+    ledger: &L,
+) -> Result<(), String> {
+    let fee = ledger.fee().await?;
+    let mut balance = ledger.balance_of(escrow).await?;
+    let (commission_receiver, commission_bps) =
+        read_config(|config| (config.commission_receiver, config.commission_bps()));
+
+    if let Some(receiver) = commission_receiver {
+        let commission = ((launch.raised as u128 * commission_bps as u128) / 10_000) as u64;
+        if balance >= launch.raised && commission > fee {
+            ledger
+                .transfer(escrow.subaccount, Account::from(receiver), commission - fee)
+                .await?;
+            balance -= commission;
+        }
+    }
+
+    let payout = balance
+        .checked_sub(fee)
+        .filter(|payout| *payout > 0)
+        .ok_or_else(|| String::from("escrow doesn't cover the ledger fee"))?;
+    let block_index = ledger
+        .transfer(escrow.subaccount, Account::from(launch.created_by), payout)
+        .await?;
+    write_launch_manager(|manager| {
+        manager.update_launch(launch.launch_id, |launch| {
+            launch.payout_txid.replace(block_index.to_string())
+        })?;
+        manager.transition(launch.launch_id, LaunchStatus::Distributed)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{block_on, contributor, insert_launch, live_launch, StandInLedger};
+
+    fn refund_txid(id: u8) -> Option<String> {
+        read_launch_manager(|manager| manager.get_contribution(1, &contributor(id)))
+            .unwrap()
+            .refund_txid
+    }
+
+    #[test]
+    fn keeps_refunds_made_before_a_failed_transfer() {
+        let launch = insert_launch(
+            live_launch(1, TokenType::Icp),
+            &[(1, 1_000), (2, 500)],
+            LaunchStatus::Failed,
+        );
+        let ledger = StandInLedger::new(10);
+        ledger.mint(ledger.escrow(), 1_000);
+
+        let result = block_on(refund_on_ledger(&launch, ledger.escrow(), &ledger));
+        assert!(result.is_err());
+        assert_eq!(refund_txid(1), Some(String::from("0")));
+        assert_eq!(refund_txid(2), None);
+
+        // the next attempt only refunds what's left, less the ledger fee
+        ledger.mint(ledger.escrow(), 500);
+        block_on(refund_on_ledger(&launch, ledger.escrow(), &ledger)).unwrap();
+        assert_eq!(ledger.balance(Account::from(contributor(1))), 990);
+        assert_eq!(ledger.balance(Account::from(contributor(2))), 490);
+        assert_eq!(refund_txid(2), Some(String::from("1")));
+    }
+}
@@ -0,0 +1,62 @@
+use candid::{CandidType, Principal};
+use ic_cdk::api::management_canister::bitcoin::BitcoinNetwork;
+use serde::Deserialize;
+use serde_bytes::ByteBuf;
+
+use crate::{
+    chain::btc::address_validation,
+    state::{read_config, read_user_manager, write_user_manager},
+};
+
+#[derive(CandidType, Deserialize)]
+pub enum GetAddressResponse {
+    Ok(String),
+    Err(String),
+}
+
+fn provider() -> Option<Principal> {
+    read_config(|config| config.siwb_provider)
+}
+
+// network names as the provider parses them
+fn network_name(network: BitcoinNetwork) -> String {
+    match network {
+        BitcoinNetwork::Mainnet => String::from("mainnet"),
+        BitcoinNetwork::Testnet => String::from("testnet"),
+        BitcoinNetwork::Regtest => String::from("regtest"),
+    }
+}
+
+pub async fn get_address(provider: Principal, principal: Principal) -> Result<String, String> {
+    let network = network_name(read_config(|config| config.bitcoin_network()));
+    let principal = ByteBuf::from(principal.as_slice().to_vec());
+    let (response,) = ic_cdk::call::<(ByteBuf, String), (GetAddressResponse,)>(
+        provider,
+        "get_address",
+        (principal, network),
+    )
+    .await
+    .map_err(|(code, msg)| format!("siwb provider rejected: {:?} {}", code, msg))?;
+    match response {
+        GetAddressResponse::Ok(address) => Ok(address),
+        GetAddressResponse::Err(err) => {
+            Err(format!("caller isn't signed in with bitcoin: {}", err))
+        }
+    }
+}
+
+// the wallet address `principal` signed in with, looked up once and cached in
+// the user registry since the provider derives principals from addresses.
+// `None` when no provider is configured
+pub async fn bind_address(principal: Principal) -> Result<Option<String>, String> {
+    if let Some(address) = read_user_manager(|manager| manager.get_user(&principal).btc_address) {
+        return Ok(Some(address));
+    }
+    let Some(provider) = provider() else {
+        return Ok(None);
+    };
+    let address = get_address(provider, principal).await?;
+    address_validation(&address)?;
+    write_user_manager(|manager| manager.bind_address(principal, address.clone()));
+    Ok(Some(address))
+}
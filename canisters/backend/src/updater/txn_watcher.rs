@@ -0,0 +1,478 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    time::Duration,
+};
+
+use bitcoin::{
+    absolute::LockTime, consensus::serialize, hashes::Hash, transaction::Version, Amount,
+    ScriptBuf, Transaction, TxOut, Txid, Witness,
+};
+use ic_cdk::api::management_canister::bitcoin::{
+    bitcoin_get_utxos, GetUtxosRequest, Outpoint, Utxo, UtxoFilter,
+};
+use icrc_ledger_types::icrc1::account::Account;
+
+use crate::{
+    chain::btc::{
+        address_validation, fee_per_vbyte,
+        runestone::etch::sign_reveal_transaction,
+        send_transaction,
+        signer::ecdsa::{ecdsa_sign_transaction, mock_ecdsa_signature},
+        transaction::utxos_to_inputs,
+        DUST_THRESHOLD,
+    },
+    guard::OnDrop,
+    state::{
+        config::FeePriority,
+        read_config, read_pending_reveals, read_submitted_txns,
+        txn_manager::{SpentInput, SubmittedTransaction},
+        utxo_manager::PendingSpend,
+        write_launch_manager, write_pending_reveals, write_submitted_txns, write_utxo_manager,
+    },
+    txn_handler::TransactionType,
+    updater::rune_indexer::replace_runic_outputs,
+};
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(600);
+// a replacement has to pay at least the minimum relay fee on top
+const MIN_FEE_INCREMENT: u64 = 1_000;
+
+thread_local! {
+    static WATCHING: RefCell<bool> = const { RefCell::new(false) };
+}
+
+// `None` while another round is running
+fn claim_round() -> Option<OnDrop<(), impl FnOnce(())>> {
+    WATCHING
+        .with_borrow_mut(|watching| !std::mem::replace(watching, true))
+        .then(|| {
+            OnDrop::new((), |_| {
+                WATCHING.with_borrow_mut(|watching| *watching = false)
+            })
+        })
+}
+
+pub fn start_watcher() {
+    ic_cdk_timers::set_timer_interval(WATCH_INTERVAL, || ic_cdk::spawn(watch_transactions()));
+}
+
+// keeps a broadcast transaction around until it's mined. `inputs` are the
+// utxos it spends, in order, and `fee_output` the output a fee bump is taken
+// from
+pub fn register_transaction(
+    txn_type: TransactionType,
+    txn: &Transaction,
+    inputs: Vec<SpentInput>,
+    fee_output: Option<u32>,
+) {
+    let mut submitted = SubmittedTransaction {
+        txn_type,
+        txn: serialize(txn),
+        inputs,
+        fee_per_vbytes: 0,
+        fee_output,
+        submitted_at: ic_cdk::api::time(),
+        child_txid: None,
+    };
+    submitted.fee_per_vbytes = (submitted.fee() * 1000) / txn.vsize() as u64;
+    write_submitted_txns(|txns| txns.insert(txn.compute_txid().to_string(), submitted));
+}
+
+async fn watch_transactions() {
+    let Some(_round) = claim_round() else {
+        return;
+    };
+    let submitted: Vec<(String, SubmittedTransaction)> =
+        read_submitted_txns(|txns| txns.iter().collect());
+    let timeout = read_config(|config| config.rbf_timeout_secs()) * 1_000_000_000;
+    // transactions spending the outputs of another pending one, like chained
+    // distribution batches
+    let parents: HashSet<String> = submitted
+        .iter()
+        .flat_map(|(_, entry)| entry.inputs.iter().map(|input| spent_txid(&input.utxo)))
+        .collect();
+
+    for (txid, entry) in submitted {
+        match is_mined(&entry).await {
+            Err(_) => continue,
+            Ok(true) => confirm_transaction(&txid, &entry),
+            // replacing a parent would drop its children, bumping the last
+            // of a chain pulls the ones before it along
+            Ok(false) if parents.contains(&txid) => {}
+            // a bump that can't be afforded is retried next round. commits
+            // are accelerated with a child so the reveal built on top of
+            // them stays valid, once they have one it's the child that times
+            // out and gets replaced
+            Ok(false) if ic_cdk::api::time() - entry.submitted_at > timeout => {
+                let _ = match &entry.txn_type {
+                    TransactionType::Etching { .. } => accelerate_again(&txid).await,
+                    TransactionType::Acceleration { parent_txid } => {
+                        accelerate_again(parent_txid).await
+                    }
+                    _ => bump_transaction(&txid, entry).await,
+                };
+            }
+            Ok(false) => {}
+        }
+    }
+}
+
+fn spent_txid(utxo: &Utxo) -> String {
+    Txid::from_slice(&utxo.outpoint.txid)
+        .map(|txid| txid.to_string())
+        .unwrap_or_default()
+}
+
+// the first input leaving the utxo set means one version of the transaction
+// got mined. an input that is the output of another pending transaction isn't
+// in the set yet, so neither can be mined
+async fn is_mined(entry: &SubmittedTransaction) -> Result<bool, String> {
+    let input = match entry.inputs.first() {
+        None => return Ok(true),
+        Some(input) => input,
+    };
+    let parent = spent_txid(&input.utxo);
+    if read_submitted_txns(|txns| txns.contains_key(&parent)) {
+        return Ok(false);
+    }
+    let network = read_config(|config| config.bitcoin_network());
+    let mut filter = None;
+
+    loop {
+        let response = bitcoin_get_utxos(GetUtxosRequest {
+            address: input.address.clone(),
+            network,
+            filter,
+        })
+        .await
+        .map_err(|(code, msg)| format!("failed to fetch utxos: {:?} {}", code, msg))?
+        .0;
+
+        if response
+            .utxos
+            .iter()
+            .any(|utxo| utxo.outpoint == input.utxo.outpoint)
+        {
+            return Ok(false);
+        }
+        match response.next_page {
+            None => return Ok(true),
+            Some(page) => filter = Some(UtxoFilter::Page(page)),
+        }
+    }
+}
+
+fn confirm_transaction(txid: &str, entry: &SubmittedTransaction) {
+    write_submitted_txns(|txns| txns.remove(&txid.to_string()));
+    if let TransactionType::Reveal { commit_txid, .. } = &entry.txn_type {
+        write_pending_reveals(|reveals| reveals.remove(commit_txid));
+    }
+}
+
+// replaces the transaction with one paying a higher fee, taken from its fee
+// output. every other output stays as it is, so a replaced commit still pays
+// the same amount to the reveal
+async fn bump_transaction(txid: &str, mut entry: SubmittedTransaction) -> Result<(), String> {
+    let fee_output = entry
+        .fee_output
+        .ok_or_else(|| String::from("transaction has no output to take a fee bump from"))?
+        as usize;
+    let mut txn = entry.transaction();
+    let fee = entry.fee();
+    let vsize = txn.vsize() as u64;
+
+    let fee_per_vbytes = next_fee_rate(entry.fee_per_vbytes).await;
+    let extra = ((vsize * fee_per_vbytes) / 1000).saturating_sub(fee);
+    let txout = &mut txn.output[fee_output];
+    txout.value = txout
+        .value
+        .checked_sub(Amount::from_sat(extra))
+        .filter(|value| *value >= txout.script_pubkey.minimal_non_dust())
+        .ok_or_else(|| String::from("fee output doesn't cover the bump"))?;
+
+    for input in txn.input.iter_mut() {
+        input.script_sig = ScriptBuf::new();
+        input.witness = Witness::new();
+    }
+    let txn = sign_replacement(&entry, txn).await?;
+    send_transaction(&txn).await?;
+
+    let replacement = txn.compute_txid();
+    entry.txn = serialize(&txn);
+    entry.fee_per_vbytes = fee_per_vbytes;
+    entry.submitted_at = ic_cdk::api::time();
+    replaced(&entry.txn_type, txid, &txn);
+    write_submitted_txns(|txns| {
+        txns.remove(&txid.to_string());
+        txns.insert(replacement.to_string(), entry);
+    });
+    Ok(())
+}
+
+// the current rate, but at least a quarter more than what was paid before
+async fn next_fee_rate(fee_per_vbytes: u64) -> u64 {
+    let increment = (fee_per_vbytes / 4).max(MIN_FEE_INCREMENT);
+    fee_per_vbyte(FeePriority::High)
+        .await
+        .max(fee_per_vbytes + increment)
+}
+
+// accelerates the transaction at a higher rate than it pays as a package
+async fn accelerate_again(txid: &str) -> Result<(), String> {
+    let entry = read_submitted_txns(|txns| txns.get(&txid.to_string()))
+        .ok_or_else(|| String::from("transaction isn't pending"))?;
+    let fee_per_vbytes = next_fee_rate(entry.fee_per_vbytes).await;
+    accelerate_transaction(txid, fee_per_vbytes).await?;
+    Ok(())
+}
+
+// child-pays-for-parent: spends the fee output of an unconfirmed transaction
+// back to its owner, paying enough for both together to reach `fee_per_vbytes`.
+// the fee output has to belong to the first input's account, like the change
+// of a commit. accelerating again replaces the previous child
+pub async fn accelerate_transaction(txid: &str, fee_per_vbytes: u64) -> Result<String, String> {
+    let mut entry = read_submitted_txns(|txns| txns.get(&txid.to_string()))
+        .ok_or_else(|| String::from("transaction isn't pending"))?;
+    let parent = entry.transaction();
+    let (owner_addr, owner, account) = match entry.inputs.first() {
+        Some(SpentInput {
+            address,
+            account: Some(account),
+            ..
+        }) => (address.clone(), address_validation(address)?, *account),
+        _ => return Err(String::from("transaction can't be accelerated")),
+    };
+    let vout = entry
+        .fee_output
+        .filter(|vout| {
+            parent
+                .output
+                .get(*vout as usize)
+                .is_some_and(|txout| txout.script_pubkey == owner.script_pubkey())
+        })
+        .ok_or_else(|| String::from("transaction has no output to accelerate it with"))?;
+    let change = Utxo {
+        outpoint: Outpoint {
+            txid: parent.compute_txid().to_byte_array().to_vec(),
+            vout,
+        },
+        value: parent.output[vout as usize].value.to_sat(),
+        height: 0,
+    };
+
+    let mut child = Transaction {
+        input: utxos_to_inputs(std::slice::from_ref(&change)),
+        output: vec![TxOut {
+            script_pubkey: owner.script_pubkey(),
+            value: Amount::from_sat(change.value),
+        }],
+        version: Version(2),
+        lock_time: LockTime::ZERO,
+    };
+    let package_vsize = parent.vsize() as u64 + mock_ecdsa_signature(&child, &owner).vsize() as u64;
+    let child_fee = ((package_vsize * fee_per_vbytes) / 1000)
+        .checked_sub(entry.fee())
+        .filter(|fee| *fee > 0)
+        .ok_or_else(|| String::from("transaction already pays this fee rate"))?;
+    child.output[0].value = child.output[0]
+        .value
+        .checked_sub(Amount::from_sat(child_fee))
+        .filter(|value| value.to_sat() >= DUST_THRESHOLD)
+        .ok_or_else(|| format!("change doesn't cover the fee of {} sats", child_fee))?;
+
+    let spent = std::slice::from_ref(&change);
+    // the change is already marked while an earlier child spends it
+    let spend = entry.child_txid.is_none().then(|| {
+        write_utxo_manager(|manager| manager.remove_bitcoin_utxos(&owner_addr, spent));
+        PendingSpend::new(&owner_addr, spent)
+    });
+    let child = ecdsa_sign_transaction(&account, &owner, child, spent).await;
+    send_transaction(&child).await?;
+    if let Some(spend) = spend {
+        spend.broadcast();
+    }
+    let child_txid = child.compute_txid().to_string();
+
+    register_transaction(
+        TransactionType::Acceleration {
+            parent_txid: txid.to_string(),
+        },
+        &child,
+        vec![SpentInput {
+            utxo: change,
+            address: owner_addr,
+            account: Some(account),
+        }],
+        None,
+    );
+    if let Some(previous) = entry.child_txid.replace(child_txid.clone()) {
+        write_submitted_txns(|txns| txns.remove(&previous));
+    }
+    entry.fee_per_vbytes = fee_per_vbytes;
+    entry.submitted_at = ic_cdk::api::time();
+    write_submitted_txns(|txns| txns.insert(txid.to_string(), entry));
+    Ok(child_txid)
+}
+
+async fn sign_replacement(
+    entry: &SubmittedTransaction,
+    txn: Transaction,
+) -> Result<Transaction, String> {
+    if let TransactionType::Reveal { commit_txid, .. } = &entry.txn_type {
+        let mut pending_reveal = read_pending_reveals(|reveals| reveals.get(commit_txid))
+            .ok_or_else(|| String::from("reveal is no longer pending"))?;
+        pending_reveal.reveal_txn = serialize(&txn);
+        return sign_reveal_transaction(&pending_reveal).await;
+    }
+
+    let mut signers: HashMap<(String, Account), Vec<Utxo>> = HashMap::new();
+    for input in entry.inputs.iter() {
+        let account = input
+            .account
+            .ok_or_else(|| String::from("input can't be signed with ecdsa"))?;
+        signers
+            .entry((input.address.clone(), account))
+            .or_default()
+            .push(input.utxo.clone());
+    }
+    let mut txn = txn;
+    for ((address, account), utxos) in signers {
+        let address = address_validation(&address)?;
+        txn = ecdsa_sign_transaction(&account, &address, txn, &utxos).await;
+    }
+    Ok(txn)
+}
+
+// points everything referring to the replaced transaction at the replacement
+fn replaced(txn_type: &TransactionType, previous: &str, txn: &Transaction) {
+    let txid = txn.compute_txid();
+    if let Ok(previous) = Txid::from_str(previous) {
+        replace_runic_outputs(&previous, txn);
+    }
+    match txn_type {
+        // commits and their children are accelerated, never replaced
+        TransactionType::Etching { .. } | TransactionType::Acceleration { .. } => {}
+        TransactionType::Reveal {
+            launch_id,
+            commit_txid,
+        } => {
+            write_pending_reveals(|reveals| {
+                if let Some(mut pending_reveal) = reveals.get(commit_txid) {
+                    pending_reveal.reveal_txid.replace(txid.to_string());
+                    reveals.insert(commit_txid.clone(), pending_reveal);
+                }
+            });
+            let premine_utxo = txn
+                .output
+                .first()
+                .filter(|txout| !txout.script_pubkey.is_op_return())
+                .map(|txout| Utxo {
+                    outpoint: Outpoint {
+                        txid: txid.to_byte_array().to_vec(),
+                        vout: 0,
+                    },
+                    value: txout.value.to_sat(),
+                    height: 0,
+                });
+            let _ = write_launch_manager(|manager| {
+                manager.update_launch(*launch_id, |launch| {
+                    launch.reveal_txid.replace(txid.to_string());
+                    launch.premine_utxo = premine_utxo;
+                })
+            });
+        }
+        TransactionType::Payout { launch_id } => {
+            let _ = write_launch_manager(|manager| {
+                manager.update_launch(*launch_id, |launch| {
+                    launch.payout_txid.replace(txid.to_string())
+                })
+            });
+        }
+        TransactionType::Refund { launch_id } => {
+            write_launch_manager(|manager| {
+                manager.replace_settlement_txid(*launch_id, previous, &txid.to_string())
+            });
+        }
+        TransactionType::Distribution { launch_id } => {
+            // the undistributed premine moves with the replacement
+            let previous_txid = Txid::from_str(previous).ok();
+            let _ = write_launch_manager(|manager| {
+                manager.replace_settlement_txid(*launch_id, previous, &txid.to_string());
+                manager.update_launch(*launch_id, |launch| {
+                    if let Some(premine) = launch.premine_utxo.as_mut() {
+                        if Txid::from_slice(&premine.outpoint.txid).ok() == previous_txid {
+                            premine.outpoint.txid = txid.to_byte_array().to_vec();
+                        }
+                    }
+                })
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        state::{launch_manager::LaunchStatus, read_launch_manager},
+        test_support::{contributor, insert_launch, live_launch},
+        token_type::TokenType,
+    };
+
+    const PREVIOUS: &str = "0101010101010101010101010101010101010101010101010101010101010101";
+
+    fn replacement() -> Transaction {
+        Transaction {
+            input: vec![],
+            output: vec![TxOut {
+                script_pubkey: ScriptBuf::new(),
+                value: Amount::from_sat(546),
+            }],
+            version: Version(2),
+            lock_time: LockTime::ZERO,
+        }
+    }
+
+    #[test]
+    fn replaced_distribution_moves_contributions_and_premine() {
+        let mut launch = live_launch(1, TokenType::Bitcoin);
+        launch.premine_utxo = Some(Utxo {
+            outpoint: Outpoint {
+                txid: Txid::from_str(PREVIOUS).unwrap().to_byte_array().to_vec(),
+                vout: 1,
+            },
+            value: 546,
+            height: 0,
+        });
+        insert_launch(launch, &[(2, 1_000), (3, 1_000)], LaunchStatus::Succeeded);
+        write_launch_manager(|manager| {
+            manager.update_contribution(1, &contributor(2), |c| {
+                c.distribution_txid.replace(PREVIOUS.to_string())
+            })
+        })
+        .unwrap();
+
+        let txn = replacement();
+        replaced(
+            &TransactionType::Distribution { launch_id: 1 },
+            PREVIOUS,
+            &txn,
+        );
+
+        let txid = txn.compute_txid();
+        let distribution_txid = |id| {
+            read_launch_manager(|manager| manager.get_contribution(1, &contributor(id)))
+                .unwrap()
+                .distribution_txid
+        };
+        assert_eq!(distribution_txid(2), Some(txid.to_string()));
+        assert_eq!(distribution_txid(3), None);
+        let premine = read_launch_manager(|manager| manager.get_launch(1))
+            .unwrap()
+            .premine_utxo
+            .unwrap();
+        assert_eq!(premine.outpoint.txid, txid.to_byte_array().to_vec());
+    }
+}
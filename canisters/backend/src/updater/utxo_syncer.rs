@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use ic_cdk::api::management_canister::bitcoin::{bitcoin_get_utxos, GetUtxosRequest, UtxoFilter};
+
+use crate::state::{read_config, read_utxo_manager, utxo_manager::SyncStatus, write_utxo_manager};
+
+use super::ord_canister::{classify_utxos, Classification};
+
+const SYNC_INTERVAL: Duration = Duration::from_secs(600);
+pub const MIN_CONFIRMATIONS: u32 = 1;
+
+pub fn start_syncer() {
+    ic_cdk_timers::set_timer_interval(SYNC_INTERVAL, || ic_cdk::spawn(sync_tracked_addresses()));
+}
+
+async fn sync_tracked_addresses() {
+    let addresses = read_utxo_manager(|manager| manager.tracked_addresses());
+    for addr in addresses {
+        // a failing address shouldn't hold back the rest, it's retried next round
+        let _ = sync_address(&addr).await;
+    }
+}
+
+pub async fn sync_address(addr: &str) -> Result<(), String> {
+    let network = read_config(|config| config.bitcoin_network());
+    let mut filter = Some(UtxoFilter::MinConfirmations(MIN_CONFIRMATIONS));
+    let (mut utxos, mut status) = (vec![], SyncStatus::default());
+
+    loop {
+        let response = bitcoin_get_utxos(GetUtxosRequest {
+            address: addr.to_string(),
+            network,
+            filter,
+        })
+        .await
+        .map_err(|(code, msg)| format!("failed to fetch utxos: {:?} {}", code, msg))?
+        .0;
+
+        utxos.extend(response.utxos);
+        if status.tip_block_hash.is_empty() {
+            status = SyncStatus {
+                tip_height: response.tip_height,
+                tip_block_hash: response.tip_block_hash,
+                synced_at: ic_cdk::api::time(),
+            };
+        }
+
+        match response.next_page {
+            None => break,
+            Some(page) => filter = Some(UtxoFilter::Page(page)),
+        }
+    }
+
+    write_utxo_manager(|manager| manager.expire_spent(addr, status.synced_at));
+    let unclassified = read_utxo_manager(|manager| manager.unclassified_utxos(addr, &utxos));
+    let Classification { runic, pending } = classify_utxos(unclassified).await?;
+
+    write_utxo_manager(|manager| manager.reconcile_utxos(addr, utxos, runic, pending, status));
+    Ok(())
+}